@@ -0,0 +1,5 @@
+CREATE TABLE webhooks (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  url TEXT NOT NULL,
+  events TEXT NOT NULL
+);
@@ -0,0 +1,9 @@
+DROP INDEX tasks_slug;
+CREATE TABLE tasks_without_slug (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  description TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT 0
+);
+INSERT INTO tasks_without_slug SELECT id, description, completed FROM tasks;
+DROP TABLE tasks;
+ALTER TABLE tasks_without_slug RENAME TO tasks;
@@ -0,0 +1,3 @@
+ALTER TABLE tasks ADD COLUMN slug TEXT NOT NULL DEFAULT '';
+UPDATE tasks SET slug = 'task-' || id;
+CREATE UNIQUE INDEX tasks_slug ON tasks (slug);
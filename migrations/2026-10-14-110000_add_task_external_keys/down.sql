@@ -0,0 +1,12 @@
+DROP INDEX tasks_external_key;
+DROP INDEX tasks_slug;
+CREATE TABLE tasks_without_external_key (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  description TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT 0,
+  slug TEXT NOT NULL DEFAULT ''
+);
+INSERT INTO tasks_without_external_key SELECT id, description, completed, slug FROM tasks;
+DROP TABLE tasks;
+ALTER TABLE tasks_without_external_key RENAME TO tasks;
+CREATE UNIQUE INDEX tasks_slug ON tasks (slug);
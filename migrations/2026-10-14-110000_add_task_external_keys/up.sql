@@ -0,0 +1,2 @@
+ALTER TABLE tasks ADD COLUMN external_key TEXT;
+CREATE UNIQUE INDEX tasks_external_key ON tasks (external_key);
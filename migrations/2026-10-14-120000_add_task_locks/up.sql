@@ -0,0 +1 @@
+ALTER TABLE tasks ADD COLUMN locked BOOLEAN NOT NULL DEFAULT 0;
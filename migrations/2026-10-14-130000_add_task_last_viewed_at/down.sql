@@ -0,0 +1,15 @@
+DROP INDEX tasks_external_key;
+DROP INDEX tasks_slug;
+CREATE TABLE tasks_without_views (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  description TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT 0,
+  slug TEXT NOT NULL DEFAULT '',
+  external_key TEXT,
+  locked BOOLEAN NOT NULL DEFAULT 0
+);
+INSERT INTO tasks_without_views SELECT id, description, completed, slug, external_key, locked FROM tasks;
+DROP TABLE tasks;
+ALTER TABLE tasks_without_views RENAME TO tasks;
+CREATE UNIQUE INDEX tasks_slug ON tasks (slug);
+CREATE UNIQUE INDEX tasks_external_key ON tasks (external_key);
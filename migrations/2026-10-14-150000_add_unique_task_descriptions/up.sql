@@ -0,0 +1,15 @@
+CREATE TEMPORARY TABLE renamed_tasks AS
+  WITH RECURSIVE attempts(task_id, candidate) AS (
+    SELECT id, description || ' (' || id || ')' FROM tasks
+      WHERE id NOT IN (SELECT MIN(id) FROM tasks GROUP BY description)
+    UNION ALL
+    SELECT task_id, candidate || ' (' || task_id || ')' FROM attempts
+      WHERE candidate IN (SELECT description FROM tasks)
+  )
+  SELECT task_id, candidate FROM attempts
+    WHERE candidate NOT IN (SELECT description FROM tasks);
+UPDATE tasks SET description = (
+  SELECT candidate FROM renamed_tasks WHERE task_id = tasks.id
+) WHERE id IN (SELECT task_id FROM renamed_tasks);
+DROP TABLE renamed_tasks;
+CREATE UNIQUE INDEX tasks_description ON tasks (description);
@@ -0,0 +1,20 @@
+DROP INDEX tasks_description_key;
+DROP INDEX tasks_external_key;
+DROP INDEX tasks_slug;
+CREATE TABLE tasks_without_description_keys (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  description TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT 0,
+  slug TEXT NOT NULL DEFAULT '',
+  external_key TEXT,
+  locked BOOLEAN NOT NULL DEFAULT 0,
+  last_viewed_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00',
+  updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00'
+);
+INSERT INTO tasks_without_description_keys SELECT id, description, completed, slug, external_key, locked, last_viewed_at, created_at, updated_at FROM tasks;
+DROP TABLE tasks;
+ALTER TABLE tasks_without_description_keys RENAME TO tasks;
+CREATE UNIQUE INDEX tasks_slug ON tasks (slug);
+CREATE UNIQUE INDEX tasks_external_key ON tasks (external_key);
+CREATE UNIQUE INDEX tasks_description ON tasks (description);
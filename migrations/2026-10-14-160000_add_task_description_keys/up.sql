@@ -0,0 +1,18 @@
+CREATE TEMPORARY TABLE renamed_tasks AS
+  WITH RECURSIVE attempts(task_id, candidate) AS (
+    SELECT id, description || ' (' || id || ')' FROM tasks
+      WHERE id NOT IN (SELECT MIN(id) FROM tasks GROUP BY lower(description))
+    UNION ALL
+    SELECT task_id, candidate || ' (' || task_id || ')' FROM attempts
+      WHERE lower(candidate) IN (SELECT lower(description) FROM tasks)
+  )
+  SELECT task_id, candidate FROM attempts
+    WHERE lower(candidate) NOT IN (SELECT lower(description) FROM tasks);
+UPDATE tasks SET description = (
+  SELECT candidate FROM renamed_tasks WHERE task_id = tasks.id
+) WHERE id IN (SELECT task_id FROM renamed_tasks);
+DROP TABLE renamed_tasks;
+ALTER TABLE tasks ADD COLUMN description_key TEXT NOT NULL DEFAULT '';
+UPDATE tasks SET description_key = lower(description);
+DROP INDEX tasks_description;
+CREATE UNIQUE INDEX tasks_description_key ON tasks (description_key);
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{errors::ErrorKind, Header, Validation};
+use rocket::{
+    config::ConfigError,
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    Rocket, State,
+};
+use serde::{Deserialize, Serialize};
+
+/// How long the tokens handed out by `POST /login` stay valid
+pub const TOKEN_LIFETIME_HOURS: i64 = 12;
+
+pub struct User {
+    /// The `sub` claim of the user's token
+    pub id: String,
+    pub role: String,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
+}
+
+/// The role of someone logging in with `password`, as `POST /login` and
+/// `BasicAuth` hand it out
+pub fn role_for(password: &str) -> &'static str {
+    if password == "admin" {
+        "admin"
+    } else {
+        "user"
+    }
+}
+
+/// Claims of the tokens signed by `Tokens`
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    role: String,
+    /// Seconds since the epoch after which the token is rejected
+    exp: i64,
+}
+
+/// Signs and verifies HS256 JSON Web Tokens with the `jwt_secret` from the
+/// configuration
+#[derive(Clone)]
+pub struct Tokens {
+    secret: Vec<u8>,
+}
+
+impl Tokens {
+    pub fn new(secret: &[u8]) -> Self {
+        Tokens {
+            secret: secret.to_vec(),
+        }
+    }
+
+    pub fn from_config(rocket: &Rocket) -> Result<Self, String> {
+        match rocket.config().get_str("jwt_secret") {
+            Ok(secret) if !secret.is_empty() => Ok(Tokens::new(secret.as_bytes())),
+            Err(ConfigError::Missing(_)) => Err(String::from(
+                "`jwt_secret` must be configured to sign tokens",
+            )),
+            _ => Err(String::from("`jwt_secret` must be a non-empty string")),
+        }
+    }
+
+    pub fn issue(&self, id: &str, role: &str, expires: DateTime<Utc>) -> String {
+        let claims = Claims {
+            sub: id.to_string(),
+            role: role.to_string(),
+            exp: expires.timestamp(),
+        };
+
+        jsonwebtoken::encode(&Header::default(), &claims, &self.secret)
+            .expect("claims serialize to JSON")
+    }
+
+    /// A token for someone logging in now
+    pub fn issue_now(&self, id: &str, role: &str) -> String {
+        self.issue(id, role, Utc::now() + Duration::hours(TOKEN_LIFETIME_HOURS))
+    }
+
+    /// The user behind a token, or why it was rejected
+    pub fn verify(&self, token: &str) -> Result<User, String> {
+        match jsonwebtoken::decode::<Claims>(token, &self.secret, &Validation::default()) {
+            Ok(data) => Ok(User {
+                id: data.claims.sub,
+                role: data.claims.role,
+            }),
+            Err(e) => Err(match e.kind() {
+                ErrorKind::ExpiredSignature => String::from("The token has expired."),
+                _ => String::from("The token is invalid."),
+            }),
+        }
+    }
+}
+
+/// A way of identifying the user behind a request. Providers are registered,
+/// in order, in the managed `AuthProviders`, and the `User` guard succeeds
+/// with the first one which recognizes the request, or fails with a 401 when
+/// that provider rejects the credentials it found.
+pub trait AuthProvider: Send + Sync {
+    /// `None` when the request carries no credentials for this provider
+    fn verify(&self, request: &Request) -> Option<Result<User, String>>;
+}
+
+pub struct AuthProviders(pub Vec<Box<dyn AuthProvider>>);
+
+/// `Authorization: Bearer <token>`, with a token signed by `Tokens`
+pub struct BearerToken(pub Tokens);
+
+impl AuthProvider for BearerToken {
+    fn verify(&self, request: &Request) -> Option<Result<User, String>> {
+        let auth = request.headers().get_one("Authorization")?;
+        if !auth.starts_with("Bearer ") {
+            return None;
+        }
+
+        Some(self.0.verify(&auth["Bearer ".len()..]))
+    }
+}
+
+/// `X-API-Key: <key>`, with one of the `api_keys` from the configuration, a
+/// table of the keys' names, which identify their users, to their key and
+/// role:
+///
+/// ```toml
+/// [global.api_keys]
+/// ci = { key = "...", role = "admin" }
+/// ```
+pub struct ApiKey(pub Vec<(String, String, String)>);
+
+impl ApiKey {
+    pub fn from_config(rocket: &Rocket) -> Result<Self, String> {
+        credentials_from_config(rocket, "api_keys", "key").map(ApiKey)
+    }
+}
+
+/// The entries of a table of credentials in the configuration, as their name
+/// along with their `secret` field and their `role`
+fn credentials_from_config(
+    rocket: &Rocket,
+    table: &str,
+    secret: &str,
+) -> Result<Vec<(String, String, String)>, String> {
+    let entries = match rocket.config().get_table(table) {
+        Ok(entries) => entries,
+        Err(ConfigError::Missing(_)) => return Ok(vec![]),
+        Err(_) => return Err(format!("`{}` must be a table", table)),
+    };
+
+    entries
+        .iter()
+        .map(|(name, entry)| {
+            let field = |field| entry.get(field).and_then(|value| value.as_str());
+            match (field(secret), field("role")) {
+                (Some(value), Some(role)) if !value.is_empty() => {
+                    Ok((name.clone(), value.to_string(), role.to_string()))
+                }
+                _ => Err(format!(
+                    "`{}.{}` must have a non-empty `{}` and a `role`",
+                    table, name, secret
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Compares secrets in time which only depends on their lengths
+fn secrets_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl AuthProvider for ApiKey {
+    fn verify(&self, request: &Request) -> Option<Result<User, String>> {
+        let given = request.headers().get_one("X-API-Key")?;
+
+        Some(
+            self.0
+                .iter()
+                .find(|(_, key, _)| secrets_match(key, given))
+                .map(|(name, _, role)| User {
+                    id: name.clone(),
+                    role: role.clone(),
+                })
+                .ok_or_else(|| String::from("The API key is invalid.")),
+        )
+    }
+}
+
+/// `Authorization: Basic <credentials>`, with the email and password of one of
+/// the `basic_users` from the configuration, a table of the users' emails to
+/// their password and role:
+///
+/// ```toml
+/// [global.basic_users]
+/// "ops@example.com" = { password = "...", role = "admin" }
+/// ```
+///
+/// Unlike `POST /login`, attempts aren't rate limited, so the passwords should
+/// be as long as API keys.
+pub struct BasicAuth(pub Vec<(String, String, String)>);
+
+impl BasicAuth {
+    pub fn from_config(rocket: &Rocket) -> Result<Self, String> {
+        credentials_from_config(rocket, "basic_users", "password").map(BasicAuth)
+    }
+}
+
+impl AuthProvider for BasicAuth {
+    fn verify(&self, request: &Request) -> Option<Result<User, String>> {
+        let auth = request.headers().get_one("Authorization")?;
+        if !auth.starts_with("Basic ") {
+            return None;
+        }
+
+        let credentials = base64::decode(&auth["Basic ".len()..])
+            .ok()
+            .and_then(|credentials| String::from_utf8(credentials).ok());
+        let mut parts = credentials
+            .as_ref()
+            .map(|credentials| credentials.splitn(2, ':'));
+        match parts.as_mut().map(|parts| (parts.next(), parts.next())) {
+            Some((Some(email), Some(password))) => Some(
+                self.0
+                    .iter()
+                    .find(|(name, known, _)| name == email && secrets_match(known, password))
+                    .map(|(name, _, role)| User {
+                        id: name.clone(),
+                        role: role.clone(),
+                    })
+                    .ok_or_else(|| String::from("The Basic credentials are invalid.")),
+            ),
+            _ => Some(Err(String::from("The Basic credentials are malformed."))),
+        }
+    }
+}
+
+/// Why the credentials of the request were rejected, for the 401 catcher
+pub struct Unauthorized(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let user = request
+            .guard::<State<AuthProviders>>()
+            .succeeded()
+            .and_then(|providers| {
+                providers
+                    .0
+                    .iter()
+                    .find_map(|provider| provider.verify(request))
+            });
+
+        match user {
+            Some(Ok(user)) => Outcome::Success(user),
+            Some(Err(reason)) => {
+                request.local_cache(|| Unauthorized(Some(reason.clone())));
+                Outcome::Failure((Status::Unauthorized, reason))
+            }
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+pub struct Admin {
+    pub user: User,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let user = request.guard::<User>()?;
+
+        if user.is_admin() {
+            Outcome::Success(Admin { user })
+        } else {
+            Outcome::Forward(())
+        }
+    }
+}
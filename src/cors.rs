@@ -0,0 +1,105 @@
+use rocket::{
+    config::ConfigError,
+    fairing::{Fairing, Info, Kind},
+    http::{Method, Status},
+    Request, Response, Rocket, State,
+};
+use std::io::Cursor;
+
+/// Prefix of the paths which browsers on other origins may call
+const PREFIX: &str = "/api/";
+
+const ALLOW_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+const ALLOW_HEADERS: &str = "Authorization, Content-Type, Prefer, X-API-Key";
+const EXPOSE_HEADERS: &str = "Location, Warning, X-Total-Count";
+
+/// Seconds browsers may cache the answer to a preflight request for
+const MAX_AGE: &str = "3600";
+
+struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    /// The `Access-Control-Allow-Origin` for requests from `origin`, if it is
+    /// allowed
+    fn allow(&self, origin: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|allowed| *allowed == "*" || *allowed == origin)
+            .cloned()
+    }
+}
+
+/// Lets browsers call the `/api` routes from the `cors_allowed_origins`, an
+/// array of origins such as `"https://todo.example.com"`, or `"*"` for any of
+/// them. Preflight `OPTIONS` requests from those origins are answered with a
+/// 204 for any `/api` path, since there are no `OPTIONS` routes.
+pub struct Cors;
+
+fn parse_origins(rocket: &Rocket) -> Result<Vec<String>, String> {
+    let origins = match rocket.config().get_slice("cors_allowed_origins") {
+        Ok(origins) => origins,
+        Err(ConfigError::Missing(_)) => return Ok(vec![]),
+        Err(_) => return Err(String::from("`cors_allowed_origins` must be an array")),
+    };
+
+    origins
+        .iter()
+        .map(|origin| {
+            origin
+                .as_str()
+                .map(|origin| origin.trim_end_matches('/').to_string())
+                .ok_or_else(|| String::from("origins must be strings"))
+        })
+        .collect()
+}
+
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Attach | Kind::Response,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        match parse_origins(&rocket) {
+            Ok(origins) => Ok(rocket.manage(AllowedOrigins(origins))),
+            Err(e) => {
+                eprintln!("Invalid `cors_allowed_origins`: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if !request.uri().path().starts_with(PREFIX) {
+            return;
+        }
+
+        let allowed = request.headers().get_one("Origin").and_then(|origin| {
+            request
+                .guard::<State<AllowedOrigins>>()
+                .succeeded()
+                .and_then(|origins| origins.allow(origin))
+        });
+        response.set_raw_header("Vary", "Origin");
+        let origin = match allowed {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        response.set_raw_header("Access-Control-Allow-Origin", origin);
+        response.set_raw_header("Access-Control-Allow-Methods", ALLOW_METHODS);
+        response.set_raw_header("Access-Control-Allow-Headers", ALLOW_HEADERS);
+        response.set_raw_header("Access-Control-Expose-Headers", EXPOSE_HEADERS);
+
+        let preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if preflight {
+            response.set_status(Status::NoContent);
+            response.set_raw_header("Access-Control-Max-Age", MAX_AGE);
+            response.remove_header("Content-Type");
+            response.set_sized_body(Cursor::new(""));
+        }
+    }
+}
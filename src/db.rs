@@ -0,0 +1,118 @@
+use diesel::{
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    SqliteConnection,
+};
+use rocket::{
+    config::Value,
+    fairing::{AdHoc, Fairing},
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    Rocket, State,
+};
+use rocket_contrib::databases::database_config;
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+/// Name of the database in the `databases` table of the configuration
+const DATABASE: &str = "tasks";
+
+/// How long a request waits for a connection when the database doesn't set a
+/// `connect_timeout_ms`
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5_000;
+
+type Manager = ConnectionManager<SqliteConnection>;
+
+struct DbPool(Pool<Manager>);
+
+/// A connection from the pool of the `tasks` database, which is configured
+/// like the databases of `rocket_contrib`, along with a `connect_timeout_ms`:
+///
+/// ```toml
+/// [global.databases]
+/// tasks = { url = "db/tasks.sqlite", pool_size = 8, connect_timeout_ms = 5000 }
+/// ```
+///
+/// Requests which don't get a connection within the timeout fail with a 503.
+pub struct DbConn(pub PooledConnection<Manager>);
+
+/// Why the service was unavailable, for the 503 catcher
+pub struct Unavailable(pub Option<String>);
+
+fn connect_timeout(extras: &BTreeMap<String, Value>) -> Result<u64, String> {
+    match extras.get("connect_timeout_ms") {
+        Some(Value::Integer(ms)) if *ms > 0 => Ok(*ms as u64),
+        Some(_) => Err(String::from(
+            "`connect_timeout_ms` must be a positive integer",
+        )),
+        None => Ok(DEFAULT_CONNECT_TIMEOUT_MS),
+    }
+}
+
+fn build_pool(rocket: &Rocket) -> Result<Pool<Manager>, String> {
+    let config = database_config(DATABASE, rocket.config()).map_err(|e| e.to_string())?;
+    let timeout = connect_timeout(&config.extras)?;
+
+    Pool::builder()
+        .max_size(config.pool_size)
+        .connection_timeout(Duration::from_millis(timeout))
+        .build(ConnectionManager::new(config.url))
+        .map_err(|e| e.to_string())
+}
+
+impl DbConn {
+    /// Sets up the pool, which the guard takes its connections from
+    pub fn fairing() -> impl Fairing {
+        AdHoc::on_attach("Database Pool", |rocket| match build_pool(&rocket) {
+            Ok(pool) => Ok(rocket.manage(DbPool(pool))),
+            Err(e) => {
+                eprintln!("Failed to set up the `{}` database: {}", DATABASE, e);
+                Err(rocket)
+            }
+        })
+    }
+
+    /// A connection outside of any request, if one becomes available in time
+    pub fn get_one(rocket: &Rocket) -> Option<Self> {
+        rocket
+            .state::<DbPool>()
+            .and_then(|pool| pool.0.get().ok())
+            .map(DbConn)
+    }
+}
+
+impl Deref for DbConn {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for DbConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for DbConn {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let pool = request.guard::<State<DbPool>>()?;
+
+        match pool.0.get() {
+            Ok(conn) => Outcome::Success(DbConn(conn)),
+            Err(_) => {
+                request.local_cache(|| {
+                    Unavailable(Some(String::from(
+                        "No database connection became available in time, try again later.",
+                    )))
+                });
+                Outcome::Failure((Status::ServiceUnavailable, ()))
+            }
+        }
+    }
+}
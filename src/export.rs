@@ -0,0 +1,83 @@
+use diesel::SqliteConnection;
+use serde::Serialize;
+use std::{
+    io::{self, Cursor, Read},
+    ops::Deref,
+};
+
+use crate::task::Task;
+
+/// Tasks fetched from the database at a time
+pub const BATCH_SIZE: i64 = 500;
+
+const HEADER: &[u8] = b"id,description,completed\n";
+
+/// A row of an exported CSV file, which `import::parse` reads back, leaving
+/// out the `id`
+#[derive(Serialize)]
+struct Row<'a> {
+    id: i32,
+    description: &'a str,
+    completed: bool,
+}
+
+/// Reads as a CSV file of every task, in ascending order of id. Tasks are
+/// fetched `BATCH_SIZE` at a time, after the last id written, and only the
+/// CSV of the current batch is kept, so tasks added during the export get
+/// included and memory use doesn't grow with the table.
+///
+/// A pooled connection is taken out of the pool for as long as the export is
+/// being read, which is until the whole response has been sent.
+pub struct Export<C> {
+    conn: C,
+    /// Id of the last task written
+    after: i32,
+    chunk: Cursor<Vec<u8>>,
+    done: bool,
+}
+
+impl<C: Deref<Target = SqliteConnection>> Export<C> {
+    pub fn new(conn: C) -> Self {
+        Export {
+            conn,
+            after: 0,
+            chunk: Cursor::new(HEADER.to_vec()),
+            done: false,
+        }
+    }
+
+    fn next_batch(&mut self) -> io::Result<Vec<u8>> {
+        let tasks = Task::after(self.after, BATCH_SIZE, &self.conn)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.done = (tasks.len() as i64) < BATCH_SIZE;
+
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        for task in &tasks {
+            writer.serialize(Row {
+                id: task.id,
+                description: &task.description,
+                completed: task.completed,
+            })?;
+            self.after = task.id;
+        }
+
+        writer
+            .into_inner()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+impl<C: Deref<Target = SqliteConnection>> Read for Export<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || self.done {
+                return Ok(read);
+            }
+
+            self.chunk = Cursor::new(self.next_batch()?);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+use crate::task::{validate_description, Todo};
+
+/// Largest CSV body accepted
+pub const LIMIT: u64 = 1 << 20;
+
+/// Rows, not counting the header, accepted in one import
+pub const MAX_ROWS: usize = 1000;
+
+/// A row of an imported CSV file, whose header names the columns
+#[derive(Deserialize)]
+struct Row {
+    description: String,
+    completed: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct Failure {
+    pub line: u64,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub inserted: usize,
+    pub failed: Vec<Failure>,
+}
+
+pub struct Parsed {
+    /// Todos, along with their line and whether they are completed
+    pub rows: Vec<(u64, Todo, bool)>,
+    pub failed: Vec<Failure>,
+}
+
+pub enum Error {
+    /// The file is longer than `LIMIT`
+    TooLarge,
+    TooManyRows,
+    Csv(csv::Error),
+}
+
+/// Parses the rows of a CSV file into todos. Malformed rows are reported with
+/// their line number rather than failing the whole import, while files over
+/// `LIMIT` are rejected before any row is parsed. Descriptions may be up to
+/// `max_len` characters long.
+pub fn parse(csv: impl Read, max_len: usize) -> Result<Parsed, Error> {
+    let mut file = vec![];
+    csv.take(LIMIT + 1)
+        .read_to_end(&mut file)
+        .map_err(|e| Error::Csv(e.into()))?;
+    if file.len() as u64 > LIMIT {
+        return Err(Error::TooLarge);
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(&file[..]);
+    let headers = reader.headers().map_err(Error::Csv)?.clone();
+    let (mut rows, mut failed) = (vec![], vec![]);
+
+    for (n, record) in reader.records().enumerate() {
+        if n == MAX_ROWS {
+            return Err(Error::TooManyRows);
+        }
+
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => match e.position() {
+                Some(position) => {
+                    failed.push(Failure {
+                        line: position.line(),
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+                None => return Err(Error::Csv(e)),
+            },
+        };
+
+        let line = record.position().map_or(0, |position| position.line());
+        match record.deserialize::<Row>(Some(&headers)) {
+            Ok(row) => match validate_description(&row.description, max_len) {
+                Ok(description) => rows.push((
+                    line,
+                    Todo {
+                        description,
+                        external_key: None,
+                    },
+                    row.completed.unwrap_or(false),
+                )),
+                Err(error) => failed.push(Failure { line, error }),
+            },
+            Err(e) => failed.push(Failure {
+                line,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(Parsed { rows, failed })
+}
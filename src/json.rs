@@ -0,0 +1,111 @@
+use rocket::{
+    data::{self, Data, FromDataSimple},
+    http::Status,
+    Outcome::{Failure, Success},
+    Request, State,
+};
+use serde::de::DeserializeOwned;
+use std::io::{self, Read};
+
+/// Default size limit for JSON bodies, matching `rocket_contrib::json::Json`
+const LIMIT: u64 = 1 << 20;
+
+/// Nesting depth allowed when `json_max_depth` is not configured
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// Maximum nesting depth of accepted JSON bodies, kept in managed state
+pub struct MaxDepth(pub usize);
+
+/// A `Json`-like data guard which rejects bodies whose arrays and objects are
+/// nested deeper than the configured `MaxDepth` with a 422, before handing the
+/// body to serde.
+pub struct ShallowJson<T>(pub T);
+
+impl<T> ShallowJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    Io(io::Error),
+    TooDeep(usize),
+    Parse(serde_json::Error),
+}
+
+/// Deepest nesting of arrays and objects in `json`, ignoring brackets inside
+/// strings. The input doesn't need to be valid JSON.
+fn depth(json: &str) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for c in json.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    max
+}
+
+impl<T: DeserializeOwned> FromDataSimple for ShallowJson<T> {
+    type Error = JsonError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let size_limit = request.limits().get("json").unwrap_or(LIMIT);
+        let max_depth = request
+            .guard::<State<MaxDepth>>()
+            .map(|max_depth| max_depth.0)
+            .succeeded()
+            .unwrap_or(DEFAULT_MAX_DEPTH);
+
+        let mut body = String::with_capacity(512);
+        if let Err(e) = data.open().take(size_limit).read_to_string(&mut body) {
+            return Failure((Status::BadRequest, JsonError::Io(e)));
+        }
+
+        let found = depth(&body);
+        if found > max_depth {
+            return Failure((Status::UnprocessableEntity, JsonError::TooDeep(found)));
+        }
+
+        match serde_json::from_str(&body) {
+            Ok(value) => Success(ShallowJson(value)),
+            Err(e) if e.is_data() => Failure((Status::UnprocessableEntity, JsonError::Parse(e))),
+            Err(e) => Failure((Status::BadRequest, JsonError::Parse(e))),
+        }
+    }
+}
+
+/// A `ShallowJson` body which may be left out: requests whose body is empty,
+/// or only whitespace, get `None` rather than a 400.
+pub struct OptionalJson<T>(pub Option<T>);
+
+impl<T: DeserializeOwned> FromDataSimple for OptionalJson<T> {
+    type Error = JsonError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        if data.peek_complete() && data.peek().iter().all(u8::is_ascii_whitespace) {
+            return Success(OptionalJson(None));
+        }
+
+        ShallowJson::from_data(request, data).map(|json| OptionalJson(Some(json.0)))
+    }
+}
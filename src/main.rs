@@ -4,29 +4,72 @@
 #[macro_use]
 extern crate diesel;
 #[macro_use]
+extern crate diesel_migrations;
+#[macro_use]
 extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
 
+mod auth;
+mod cors;
+mod db;
+mod export;
+mod import;
+mod json;
+mod maintenance;
+mod markdown;
+mod outcomes;
+mod rate_limit;
+mod request_log;
+mod retry;
 mod schema;
 mod task;
+mod webhook;
 
-use diesel::SqliteConnection;
+use auth::{Admin, ApiKey, AuthProviders, BasicAuth, BearerToken, Tokens, Unauthorized, User};
+use chrono::Utc;
+use cors::Cors;
+use db::{DbConn, Unavailable};
+use diesel::{QueryResult, SqliteConnection};
+use export::Export;
+use json::{MaxDepth, OptionalJson, ShallowJson};
+use maintenance::{Maintenance, SystemClock};
+use outcomes::{Outcomes, RecordOutcomes};
+use rate_limit::{LoginAttempt, LoginLimiter, RetryAfter};
+use request_log::{LogSink, RequestLog, Sink};
+use retry::{RetryPolicy, RetryStats};
 use rocket::{
-    http::{RawStr, Status},
-    request::{Form, FromParam, FromRequest, Outcome, Request},
+    config::ConfigError,
+    fairing::AdHoc,
+    http::{
+        uri::{self, FromUriParam},
+        ContentType, Cookie, Cookies, Header, RawStr, Status,
+    },
+    request::{Form, FromFormValue, FromParam, FromRequest, LenientForm, Outcome, Request},
     response::{
+        self,
+        content::Content,
         status::{Created, Custom},
-        Redirect,
+        NamedFile, Redirect, Responder, Stream,
     },
-    Rocket,
+    Data, Rocket, State,
 };
 use rocket_contrib::{
+    databases::database_config,
     json::{Json, JsonValue},
-    templates::Template,
+    serve::StaticFiles,
+    templates::{Metadata, Template},
 };
-use serde::Serialize;
-use task::{Task, Todo};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+use task::{SortField, Task, TaskPatch, Todo, Warning};
+use webhook::{Dispatcher, Event, NewWebhook, Webhook};
 
 struct Age(i32);
 
@@ -44,63 +87,538 @@ impl<'r> FromParam<'r> for Age {
     }
 }
 
-struct User {
-    token: String,
+/// The id of a task, which is never below 1, so that routes taking one don't
+/// match other numbers rather than looking them up
+#[derive(Clone, Copy)]
+struct TaskId(i32);
+
+impl<'r> FromParam<'r> for TaskId {
+    type Error = &'r RawStr;
+
+    fn from_param(param: &'r RawStr) -> Result<Self, Self::Error> {
+        let value = i32::from_param(param)?;
+
+        if value >= 1 {
+            Ok(TaskId(value))
+        } else {
+            Err(param)
+        }
+    }
 }
 
-impl User {
-    fn is_admin(&self) -> bool {
-        self.token.contains("admin")
+impl FromUriParam<uri::Path, i32> for TaskId {
+    type Target = i32;
+
+    fn from_uri_param(id: i32) -> i32 {
+        id
     }
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for User {
-    type Error = !;
+/// Whether the API currently rejects writes
+struct ReadOnly(AtomicBool);
+
+/// Guard for routes which modify tasks: fails with a 503 while the API is in
+/// read-only mode.
+struct Writable;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Writable {
+    type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        if let Some(auth) = request.headers().get_one("Authorization") {
-            let token = auth.replace("Bearer ", "");
-            Outcome::Success(User { token })
+        let read_only = request.guard::<State<ReadOnly>>()?;
+
+        if read_only.0.load(Ordering::SeqCst) {
+            Outcome::Failure((Status::ServiceUnavailable, ()))
         } else {
-            Outcome::Forward(())
+            Outcome::Success(Writable)
+        }
+    }
+}
+
+/// How the deployment was configured at launch. The database file is only
+/// used to measure it and, like the rest of the database URL, never reported.
+struct Deployment {
+    environment: String,
+    pool_size: u32,
+    database_file: Option<PathBuf>,
+    started: Instant,
+}
+
+#[derive(Serialize)]
+struct DbStats {
+    /// Rows in each table
+    tables: BTreeMap<&'static str, i64>,
+    /// Size on disk, unless the database is in memory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Diagnostics {
+    environment: String,
+    database: &'static str,
+    pool_size: u32,
+    template_reload: bool,
+    read_only: bool,
+    json_max_depth: usize,
+    db_retries: RetryStats,
+    uptime_secs: u64,
+}
+
+#[derive(FromForm)]
+struct Auth {
+    email: String,
+    password: String,
+}
+
+/// Themes which the `theme` cookie can select
+#[derive(FromFormValue, Clone, Copy)]
+enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn as_str(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+}
+
+#[derive(FromForm)]
+struct ThemeChoice {
+    theme: Theme,
+}
+
+embed_migrations!();
+
+/// Shape of the body returned by mutating routes
+#[derive(FromFormValue, Clone, Copy)]
+enum Return {
+    /// The affected task, which is the default
+    Representation,
+    /// Only the id of the affected task
+    Minimal,
+    /// The first page of tasks, as `GET /todos` lists them without parameters
+    List,
+}
+
+#[derive(FromForm)]
+struct MutationParams {
+    #[form(field = "return")]
+    shape: Option<Return>,
+}
+
+/// The `return=minimal` or `return=representation` preference of a `Prefer`
+/// header (RFC 7240), if it has one
+struct Prefer(Option<Return>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Prefer {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let shape = request
+            .headers()
+            .get("Prefer")
+            .flat_map(|prefer| prefer.split(|c| c == ',' || c == ';'))
+            .find_map(
+                |preference| match preference.trim().to_ascii_lowercase().as_str() {
+                    "return=minimal" => Some(Return::Minimal),
+                    "return=representation" => Some(Return::Representation),
+                    _ => None,
+                },
+            );
+
+        Outcome::Success(Prefer(shape))
+    }
+}
+
+impl MutationParams {
+    /// `?return=` wins over a `Prefer` header
+    fn shape(&self, prefer: &Prefer) -> Return {
+        self.shape.or(prefer.0).unwrap_or(Return::Representation)
+    }
+}
+
+#[derive(Serialize)]
+struct MinimalTask {
+    id: i32,
+}
+
+/// A body along with the warnings about the input which produced it, which
+/// are left out when there are none
+#[derive(Serialize)]
+struct WithWarnings<T> {
+    #[serde(flatten)]
+    body: T,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Warning>,
+}
+
+impl<T> From<T> for WithWarnings<T> {
+    fn from(body: T) -> Self {
+        WithWarnings {
+            body,
+            warnings: vec![],
         }
     }
 }
 
-struct Admin {
-    user: User,
+#[derive(Responder)]
+enum TaskBody {
+    Task(Json<WithWarnings<Task>>),
+    Minimal(Json<WithWarnings<MinimalTask>>),
+    List(Json<Vec<Task>>),
+}
+
+impl TaskBody {
+    fn new(task: Task, shape: Return, conn: &SqliteConnection) -> Result<Self, task::Error> {
+        Ok(match shape {
+            Return::Representation => TaskBody::Task(Json(task.into())),
+            Return::Minimal => TaskBody::Minimal(Json(MinimalTask { id: task.id }.into())),
+            Return::List => TaskBody::List(Json(first_page(conn)?)),
+        })
+    }
+
+    /// Adds the warnings to the body, unless it is a list, which has nowhere
+    /// to hold them
+    fn warn(mut self, warnings: Vec<Warning>) -> Self {
+        match &mut self {
+            TaskBody::Task(Json(body)) => body.warnings = warnings,
+            TaskBody::Minimal(Json(body)) => body.warnings = warnings,
+            TaskBody::List(_) => {}
+        }
+        self
+    }
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+/// Prefix the matched route is mounted at, without a trailing slash
+struct Mount(String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Mount {
     type Error = !;
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        let user = request.guard::<User>()?;
+        let base = request.route().map_or("", |route| route.base());
+        Outcome::Success(Mount(base.trim_end_matches('/').to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct Link {
+    href: String,
+    method: &'static str,
+}
 
-        if user.is_admin() {
-            Outcome::Success(Admin { user })
+#[derive(Serialize)]
+struct TaskLinks {
+    #[serde(rename = "self")]
+    this: Link,
+    toggle: Link,
+    delete: Link,
+}
+
+/// A task as returned by the read routes, which embed `_links` to the task's
+/// routes, under the prefix the request was served from, with `?links=true`.
+#[derive(Serialize)]
+struct TaskView {
+    #[serde(flatten)]
+    task: Task,
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    links: Option<TaskLinks>,
+}
+
+impl TaskView {
+    fn new(task: Task, links: Option<bool>, mount: &Mount) -> Self {
+        let links = if links.unwrap_or(false) {
+            let href = format!(
+                "{}{}",
+                mount.0,
+                uri!(get_task: id = task.id, links = _, touch = _)
+            );
+            let link = |method| Link {
+                href: href.clone(),
+                method,
+            };
+            Some(TaskLinks {
+                this: link("GET"),
+                toggle: link("PUT"),
+                delete: link("DELETE"),
+            })
         } else {
-            Outcome::Forward(())
+            None
+        };
+
+        TaskView { task, links }
+    }
+}
+
+/// Alternative layouts of `GET /todos`, which responds with an array of task
+/// objects by default, including for unknown `format`s
+#[derive(FromFormValue)]
+enum Layout {
+    /// One array per field, index-aligned, which is smaller to send and faster
+    /// to parse for large lists
+    Columnar,
+}
+
+#[derive(Serialize)]
+struct Columns {
+    ids: Vec<i32>,
+    descriptions: Vec<String>,
+    completed: Vec<bool>,
+}
+
+#[derive(Responder)]
+enum TaskList {
+    Rows(Json<Vec<TaskView>>),
+    Columns(Json<Columns>),
+}
+
+/// `true` or `false`, unlike `bool` which also accepts `on` and `off`, and
+/// which query parameters turn into `None` when they are anything else
+struct StrictBool(bool);
+
+impl<'v> FromFormValue<'v> for StrictBool {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "true" => Ok(StrictBool(true)),
+            "false" => Ok(StrictBool(false)),
+            _ => Err(value),
         }
     }
 }
 
+/// Directions of `GET /todos?sort=`
+#[derive(FromFormValue, PartialEq, Clone, Copy)]
+enum Order {
+    Asc,
+    Desc,
+}
+
 #[derive(FromForm)]
-struct Auth {
-    email: String,
-    password: String,
+struct ListParams<'f> {
+    links: Option<bool>,
+    format: Option<Layout>,
+    limit: Option<Result<i64, &'f RawStr>>,
+    offset: Option<Result<i64, &'f RawStr>>,
+    completed: Option<Result<StrictBool, &'f RawStr>>,
+    sort: Option<Result<SortField, &'f RawStr>>,
+    order: Option<Result<Order, &'f RawStr>>,
 }
 
-#[database("tasks")]
-struct DbConn(SqliteConnection);
+/// Tasks created at once when `max_batch_size` is not configured
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
 
-#[derive(Serialize)]
+/// What requests are held to, from the `max_description_len` and
+/// `max_batch_size` of the configuration. `GET /limits` reports them, so that
+/// clients can check their input the same way.
+#[derive(Serialize, Clone, Copy)]
+struct Limits {
+    max_description_len: usize,
+    max_batch_size: usize,
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// The tasks `GET /todos` lists without parameters: the newest ones, up to
+/// `DEFAULT_PAGE_SIZE` of them
+fn first_page(conn: &SqliteConnection) -> QueryResult<Vec<Task>> {
+    Task::page(None, SortField::Id, false, DEFAULT_PAGE_SIZE, 0, conn)
+}
+
+#[derive(Responder)]
+struct Page {
+    list: TaskList,
+    total: Header<'static>,
+}
+
+/// Errors of routes checking their input beyond what their guards do
+#[derive(Responder, Debug)]
+enum RequestError {
+    /// A 400, with the reason
+    Invalid(Custom<Json<ApiError>>),
+    Task(task::Error),
+}
+
+impl RequestError {
+    fn invalid(message: String) -> Self {
+        RequestError::Invalid(Custom(
+            Status::BadRequest,
+            ApiError::with_message(Status::BadRequest, message),
+        ))
+    }
+}
+
+impl From<task::Error> for RequestError {
+    fn from(error: task::Error) -> Self {
+        RequestError::Task(error)
+    }
+}
+
+impl From<diesel::result::Error> for RequestError {
+    fn from(error: diesel::result::Error) -> Self {
+        RequestError::Task(error.into())
+    }
+}
+
+/// Number of tasks above which lists come with a `Warning`, when
+/// `list_warning_rows` is not configured. It can't be above `MAX_PAGE_SIZE`,
+/// the most tasks a list holds.
+const DEFAULT_SOFT_LIMIT: usize = 100;
+
+/// Number of tasks a list can hold before its response warns the client, who
+/// still receives every task, that it should fetch less at once
+struct SoftLimit(usize);
+
+/// A response with a `Warning: 299` header (RFC 7234) carrying the text, if any
+struct Warned<R>(R, Option<String>);
+
+impl<'r, R: Responder<'r>> Responder<'r> for Warned<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.0.respond_to(request)?;
+        if let Some(text) = self.1 {
+            response.set_raw_header("Warning", format!("299 - \"{}\"", text));
+        }
+        Ok(response)
+    }
+}
+
+#[derive(Responder)]
+enum Creation {
+    Created(Created<TaskBody>),
+    Existing(TaskBody),
+}
+
+#[derive(Responder)]
+enum Deleted {
+    #[response(status = 204)]
+    Nothing(()),
+    List(Json<Vec<Task>>),
+}
+
+#[derive(Serialize, Debug)]
 struct ApiError {
     code: usize,
     name: String,
     message: String,
 }
 
+impl ApiError {
+    /// The error body for a status, with the usual message for it
+    fn new(status: Status) -> Json<ApiError> {
+        let message = match status.code {
+            400 => "The request could not be understood, such as a body which isn't valid JSON.",
+            401 => "The request requires authentication.",
+            404 => "Four, oh four!",
+            422 => {
+                "The request was well-formed but was unable to be followed due to semantic errors."
+            }
+            423 => "The task is locked and must be unlocked before it can change.",
+            429 => "Too many attempts, try again later.",
+            500 => "Something went wrong while handling the request.",
+            503 => "The service is read-only, try again later.",
+            _ => status.reason,
+        };
+        ApiError::with_message(status, message.to_string())
+    }
+
+    fn with_message(status: Status, message: String) -> Json<ApiError> {
+        Json(ApiError {
+            code: status.code as usize,
+            name: String::from(status.reason),
+            message,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Markdown {
+    text: String,
+}
+
+/// Body of `PUT /todos/<id>`, for the state the task should end up in
+#[derive(Deserialize)]
+struct Completion {
+    completed: bool,
+}
+
+#[derive(Serialize)]
+struct Counts {
+    total: i64,
+    completed: i64,
+    pending: i64,
+}
+
+#[derive(Serialize)]
+struct Duplicate {
+    description: String,
+    ids: Vec<i32>,
+}
+
+/// Missing and locked tasks, and invalid input, are left to their catchers, while database errors
+/// are logged and reported as a 500 right away.
+impl<'r> Responder<'r> for task::Error {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            task::Error::NotFound => Err(Status::NotFound),
+            task::Error::Locked => Err(Status::Locked),
+            task::Error::Duplicate => Custom(
+                Status::Conflict,
+                ApiError::with_message(
+                    Status::Conflict,
+                    String::from("Another task already has this description."),
+                ),
+            )
+            .respond_to(request),
+            task::Error::Invalid(reason) => {
+                request.local_cache(|| Invalid(Some(reason)));
+                Err(Status::UnprocessableEntity)
+            }
+            task::Error::Database(e) => {
+                log::error!("Database error: {}", e);
+                let error = ApiError::with_message(
+                    Status::InternalServerError,
+                    String::from("The database failed to handle the request."),
+                );
+                Custom(Status::InternalServerError, error).respond_to(request)
+            }
+        }
+    }
+}
+
+/// Methods supported on a path, reported in the `Allow` header of 405s
+struct Allow(Option<&'static str>);
+
+const TASKS_ALLOW: &str = "GET, POST";
+const TASK_ALLOW: &str = "GET, PUT, PATCH, DELETE";
+
+/// Why a request was rejected with a 422, when it's known
+struct Invalid(Option<String>);
+
+/// Responds with a 405, leaving the methods the path does support for the
+/// `method_not_allowed` catcher to report.
+struct NotAllowed(&'static str);
+
+impl<'r> Responder<'r> for NotAllowed {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        request.local_cache(|| Allow(Some(self.0)));
+        Err(Status::MethodNotAllowed)
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 405)]
+struct MethodNotAllowed {
+    error: Json<ApiError>,
+    allow: Header<'static>,
+}
+
 /// Hello world
 #[get("/")]
 fn hello() -> &'static str {
@@ -134,6 +652,104 @@ fn admin_dashboard(_admin: Admin) -> String {
     String::from("Welcome, administrator!")
 }
 
+/// Maintenance mode
+#[post("/admin/readonly", format = "json", data = "<on>")]
+fn set_read_only(_admin: Admin, on: Json<bool>, read_only: State<ReadOnly>) -> JsonValue {
+    read_only.0.store(*on, Ordering::SeqCst);
+    json!({ "read_only": *on })
+}
+
+/// Whether the database answers a trivial query
+fn database_reachable(conn: &SqliteConnection) -> bool {
+    use diesel::RunQueryDsl;
+
+    diesel::sql_query("SELECT 1").execute(conn).is_ok()
+}
+
+/// Readiness, for load balancers: a 200 once a pooled connection answers a
+/// query, and a 503 when none can be had or the query fails.
+#[get("/health")]
+fn health(conn: Option<DbConn>) -> Custom<JsonValue> {
+    match conn {
+        Some(ref conn) if database_reachable(conn) => Custom(Status::Ok, json!({ "status": "ok" })),
+        _ => Custom(
+            Status::ServiceUnavailable,
+            json!({ "status": "unavailable" }),
+        ),
+    }
+}
+
+/// Liveness, for process supervisors: a 200 whenever the process answers,
+/// without touching the database, and even during maintenance windows.
+#[get("/health/live")]
+fn health_live() -> JsonValue {
+    json!({ "status": "ok" })
+}
+
+#[get("/limits")]
+fn limits(limits: State<Limits>) -> Json<Limits> {
+    Json(*limits)
+}
+
+#[get("/admin/diagnostics")]
+fn diagnostics(
+    _admin: Admin,
+    deployment: State<Deployment>,
+    read_only: State<ReadOnly>,
+    max_depth: State<MaxDepth>,
+    retry: State<RetryPolicy>,
+) -> Json<Diagnostics> {
+    Json(Diagnostics {
+        environment: deployment.environment.clone(),
+        database: "sqlite",
+        pool_size: deployment.pool_size,
+        // Templates are only reloaded from disk by debug builds
+        template_reload: cfg!(debug_assertions),
+        read_only: read_only.0.load(Ordering::SeqCst),
+        json_max_depth: max_depth.0,
+        db_retries: retry.stats(),
+        uptime_secs: deployment.started.elapsed().as_secs(),
+    })
+}
+
+#[get("/admin/db-stats")]
+fn db_stats(
+    _admin: Admin,
+    deployment: State<Deployment>,
+    conn: DbConn,
+) -> Result<Json<DbStats>, task::Error> {
+    let mut tables = BTreeMap::new();
+    tables.insert("tasks", Task::count(None, &conn)?);
+    tables.insert("webhooks", Webhook::count(&conn)?);
+
+    let file_size = deployment
+        .database_file
+        .as_ref()
+        .and_then(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len());
+
+    Ok(Json(DbStats { tables, file_size }))
+}
+
+/// Share of the responses over the last `minutes` (5 by default) which were
+/// server errors
+#[get("/admin/error-rate?<minutes>")]
+fn error_rate(
+    _admin: Admin,
+    minutes: Option<i64>,
+    outcomes: State<Outcomes>,
+) -> Result<Json<outcomes::Summary>, RequestError> {
+    let minutes = minutes.unwrap_or(5);
+    if minutes < 1 || minutes > outcomes::WINDOW_MINUTES {
+        return Err(RequestError::invalid(format!(
+            "`minutes` must be between 1 and {}.",
+            outcomes::WINDOW_MINUTES
+        )));
+    }
+
+    Ok(Json(outcomes.summary(Utc::now(), minutes)))
+}
+
 #[get("/admin", rank = 2)]
 fn user_dashboard(_user: User) -> String {
     String::from("Welcome, simple user!")
@@ -145,128 +761,1086 @@ fn unauthenticated_user() -> Redirect {
 }
 
 /// Tera Templates
+///
+/// Pages are rendered with the `<name>.<theme>` variant of their template for
+/// the `theme` cookie, when there is one, and the default template otherwise.
 #[get("/login")]
-fn login_page() -> Template {
-    Template::render("login", json!({}))
+fn login_page(cookies: Cookies, templates: Metadata) -> Template {
+    Template::render(themed("login", &cookies, &templates), json!({}))
 }
 
-#[post("/login", data = "<auth>")]
-fn login(auth: Form<Auth>) -> JsonValue {
-    if auth.password == "admin" {
-        json!({ "token": "admin" })
-    } else {
-        json!({ "token": "hugo" })
+fn themed(name: &'static str, cookies: &Cookies, templates: &Metadata) -> String {
+    cookies
+        .get("theme")
+        .and_then(|cookie| Theme::from_form_value(RawStr::from_str(cookie.value())).ok())
+        .map(|theme| format!("{}.{}", name, theme.as_str()))
+        .filter(|variant| templates.contains_template(variant))
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Tasks as an HTML page for browsers, whose `Accept` header prefers HTML,
+/// and as JSON for everyone else
+struct TaskPage(Vec<Task>);
+
+impl<'r> Responder<'r> for TaskPage {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let html = request
+            .accept()
+            .map_or(false, |accept| accept.preferred().media_type().is_html());
+        let mut response = if html {
+            Template::render("tasks", json!({ "tasks": self.0 })).respond_to(request)?
+        } else {
+            Json(self.0).respond_to(request)?
+        };
+
+        response.set_raw_header("Vary", "Accept");
+        Ok(response)
     }
 }
 
-/// CRUD (DB access, JSON, Responders)
 #[get("/todos")]
-fn get_tasks(conn: DbConn) -> Json<Vec<Task>> {
-    Json(Task::all(&conn))
+fn task_list(conn: DbConn) -> Result<TaskPage, task::Error> {
+    Ok(TaskPage(Task::all(&conn)?))
 }
 
-#[get("/todos/<id>")]
-fn get_task(id: i32, conn: DbConn) -> Option<Json<Task>> {
-    Task::get_one(id, &conn).map(Json)
+#[post("/theme", data = "<choice>")]
+fn set_theme(choice: Form<ThemeChoice>, mut cookies: Cookies) -> Redirect {
+    cookies.add(Cookie::new("theme", choice.theme.as_str()));
+    Redirect::to(uri!(login_page))
 }
 
-#[put("/todos/<id>")]
-fn toggle_task(id: i32, conn: DbConn) -> Option<Json<Task>> {
-    Task::toggle_with_id(id, &conn).map(Json)
-}
+/// Hands out a token, signed with the `jwt_secret`, for the `Authorization:
+/// Bearer` header, which expires after `auth::TOKEN_LIFETIME_HOURS`. Clients
+/// making too many attempts get a 429 instead, see `rate_limit::LoginAttempt`.
+#[post("/login", data = "<auth>")]
+fn login(_attempt: LoginAttempt, auth: Form<Auth>, tokens: State<Tokens>) -> JsonValue {
+    let role = auth::role_for(&auth.password);
 
-#[post("/todos", format = "json", data = "<todo>")]
-fn create_task(todo: Json<Todo>, conn: DbConn) -> Option<Created<Json<Task>>> {
-    Task::insert(todo.into_inner(), &conn).map(|task| {
-        Created(
-            uri!("/api", get_task: id = task.id).to_string(),
-            Some(Json(task)),
-        )
-    })
+    json!({ "token": tokens.issue_now(&auth.email, role) })
 }
 
-#[delete("/todos/<id>")]
-fn delete_task(id: i32, conn: DbConn) -> Option<Custom<()>> {
-    if Task::delete_with_id(id, &conn) {
-        Some(Custom(Status::NoContent, ()))
+/// CRUD (DB access, JSON, Responders)
+///
+/// Lists are paginated, newest tasks first, with `?limit=` (50 by default, up
+/// to `MAX_PAGE_SIZE`) and `?offset=`, and report the number of tasks across
+/// all pages in `X-Total-Count`. `?completed=true` or `?completed=false` only
+/// lists the tasks which are, or aren't, completed.
+///
+/// `?sort=` orders them by `id`, `description` or `completed` instead, with
+/// `?order=asc` (the default once sorting) or `?order=desc`.
+#[get("/todos?<params..>")]
+fn get_tasks(
+    params: LenientForm<ListParams>,
+    mount: Mount,
+    soft_limit: State<SoftLimit>,
+    conn: DbConn,
+) -> Result<Warned<Page>, RequestError> {
+    let integer = |param: Option<Result<i64, &RawStr>>, default| match param {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(value)) => Err(RequestError::invalid(format!(
+            "`limit` and `offset` must be integers, not `{}`.",
+            value
+        ))),
+        None => Ok(default),
+    };
+    let limit = integer(params.limit, DEFAULT_PAGE_SIZE)?;
+    let offset = integer(params.offset, 0)?;
+    if limit < 0 || limit > MAX_PAGE_SIZE || offset < 0 {
+        return Err(RequestError::invalid(format!(
+            "`limit` must be between 0 and {}, and `offset` can't be negative.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let completed = match params.completed {
+        Some(Ok(StrictBool(completed))) => Some(completed),
+        Some(Err(value)) => {
+            return Err(RequestError::invalid(format!(
+                "`completed` must be `true` or `false`, not `{}`.",
+                value
+            )))
+        }
+        None => None,
+    };
+    let sort = match params.sort {
+        Some(Ok(sort)) => Some(sort),
+        Some(Err(value)) => {
+            return Err(RequestError::invalid(format!(
+                "`sort` must be `id`, `description` or `completed`, not `{}`.",
+                value
+            )))
+        }
+        None => None,
+    };
+    let ascending = match params.order {
+        Some(Ok(order)) => order == Order::Asc,
+        Some(Err(value)) => {
+            return Err(RequestError::invalid(format!(
+                "`order` must be `asc` or `desc`, not `{}`.",
+                value
+            )))
+        }
+        None => sort.is_some(),
+    };
+
+    let sort = sort.unwrap_or(SortField::Id);
+    let tasks = Task::page(completed, sort, ascending, limit, offset, &conn)?;
+    let total = Task::count(completed, &conn)?;
+    let warning = if tasks.len() > soft_limit.0 {
+        Some(format!(
+            "{} tasks returned, above the soft limit of {}; consider paginating",
+            tasks.len(),
+            soft_limit.0
+        ))
     } else {
         None
-    }
-}
+    };
 
-/// Error catchers
-#[catch(404)]
-fn not_found(_: &Request) -> Json<ApiError> {
-    Json(ApiError {
-        code: 404,
-        name: String::from("Not Found"),
-        message: String::from("Four, oh four!"),
-    })
-}
+    let list = match params.format {
+        Some(Layout::Columnar) => TaskList::Columns(Json(Columns {
+            ids: tasks.iter().map(|task| task.id).collect(),
+            descriptions: tasks.iter().map(|task| task.description.clone()).collect(),
+            completed: tasks.iter().map(|task| task.completed).collect(),
+        })),
+        None => TaskList::Rows(Json(
+            tasks
+                .into_iter()
+                .map(|task| TaskView::new(task, params.links, &mount))
+                .collect(),
+        )),
+    };
 
-#[catch(422)]
-fn unprocessable_entity(_: &Request) -> Json<ApiError> {
-    Json(ApiError {
-        code: 422,
-        name: String::from("Unprocessable Entity"),
-        message: String::from(
-            "The request was well-formed but was unable to be followed due to semantic errors.",
-        ),
-    })
+    let page = Page {
+        list,
+        total: Header::new("X-Total-Count", total.to_string()),
+    };
+    Ok(Warned(page, warning))
 }
 
-/// Rocket instance
-fn ignite_rocket() -> Rocket {
-    rocket::ignite()
-        .attach(DbConn::fairing())
-        .attach(Template::fairing())
-        .mount(
-            "/",
-            routes![
-                hello,
-                person,
-                adult,
-                child,
-                admin_dashboard,
-                user_dashboard,
-                unauthenticated_user,
-                login_page,
-                login
-            ],
-        )
-        .mount(
-            "/api",
-            routes![get_tasks, get_task, create_task, toggle_task, delete_task],
-        )
-        .register(catchers![not_found, unprocessable_entity])
+#[get("/todos/count")]
+fn get_counts(conn: DbConn) -> Result<Json<Counts>, task::Error> {
+    let (total, completed, pending) = Task::counts(&conn)?;
+    Ok(Json(Counts {
+        total,
+        completed,
+        pending,
+    }))
 }
 
-fn main() {
-    ignite_rocket().launch();
+#[get("/todos/duplicates")]
+fn get_duplicates(conn: DbConn) -> Result<Json<Vec<Duplicate>>, task::Error> {
+    Ok(Json(
+        Task::find_duplicates(&conn)?
+            .into_iter()
+            .map(|(description, ids)| Duplicate { description, ids })
+            .collect(),
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::ignite_rocket;
-    use rocket::{
-        http::{Header, Status},
-        local::Client,
+#[get("/todos/search?<q>")]
+fn search_tasks(
+    q: Option<String>,
+    mount: Mount,
+    conn: DbConn,
+) -> Result<Json<Vec<TaskView>>, RequestError> {
+    let query = match q {
+        Some(ref q) if !q.trim().is_empty() => q.trim(),
+        _ => {
+            return Err(RequestError::invalid(String::from(
+                "`q` must contain something to search for.",
+            )))
+        }
     };
 
-    #[test]
-    fn hello() {
-        let rocket = ignite_rocket();
-        let client = Client::new(rocket).unwrap();
+    Ok(Json(
+        Task::search(query, &conn)?
+            .into_iter()
+            .map(|task| TaskView::new(task, None, &mount))
+            .collect(),
+    ))
+}
 
-        let mut response = client.get("/").dispatch();
+#[get("/todos/recent-views?<limit>")]
+fn get_recent_views(
+    limit: Option<i64>,
+    mount: Mount,
+    conn: DbConn,
+) -> Result<Json<Vec<TaskView>>, task::Error> {
+    let limit = limit.unwrap_or(10).max(0).min(MAX_PAGE_SIZE);
+    Ok(Json(
+        Task::recently_viewed(limit, &conn)?
+            .into_iter()
+            .map(|task| TaskView::new(task, None, &mount))
+            .collect(),
+    ))
+}
 
-        assert_eq!(response.status(), Status::Ok);
+#[get("/todos/slug/<slug>?<links>")]
+fn get_task_by_slug(
+    slug: String,
+    links: Option<bool>,
+    mount: Mount,
+    conn: DbConn,
+) -> Result<Json<TaskView>, task::Error> {
+    let task = Task::get_by_slug(&slug, &conn)?;
+    Ok(Json(TaskView::new(task, links, &mount)))
+}
+
+/// Unlike other reads, `?touch=true` has a side-effect: it records the view in
+/// the task's `last_viewed_at`, as listed by `GET /todos/recent-views`. Failing
+/// to record a view, or being in read-only mode, doesn't fail the read.
+#[get("/todos/<id>?<links>&<touch>")]
+fn get_task(
+    id: TaskId,
+    links: Option<bool>,
+    touch: Option<bool>,
+    read_only: State<ReadOnly>,
+    mount: Mount,
+    conn: DbConn,
+) -> Result<Json<TaskView>, task::Error> {
+    let TaskId(id) = id;
+    if touch.unwrap_or(false) && !read_only.0.load(Ordering::SeqCst) {
+        if let Err(e) = Task::touch(id, &conn) {
+            log::warn!("Failed to record a view of task {}: {}", id, e);
+        }
+    }
+
+    let task = Task::get_one(id, &conn)?;
+    Ok(Json(TaskView::new(task, links, &mount)))
+}
+
+/// Mutations respond with the affected task (or nothing, for deletes) by
+/// default. With `?return=list` they respond with the first page of tasks
+/// instead, as `GET /todos` lists it, so clients can refresh their view in one
+/// request.
+/// Creates and toggles respond with only `{"id": n}` given `?return=minimal`
+/// or a `Prefer: return=minimal` header; `?return=` takes precedence.
+///
+/// Without a body, `PUT /todos/<id>` toggles the task. With a `{"completed":
+/// true}` body it sets the task to that state instead, which is safe to retry.
+#[put("/todos/<id>?<params..>", data = "<completion>")]
+#[allow(clippy::too_many_arguments)]
+fn toggle_task(
+    id: TaskId,
+    completion: OptionalJson<Completion>,
+    params: Form<MutationParams>,
+    prefer: Prefer,
+    _writable: Writable,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<TaskBody, task::Error> {
+    let TaskId(id) = id;
+    let (task, changed) = match completion.0 {
+        Some(Completion { completed }) => {
+            retry.run(|| Task::set_completed(id, completed, &conn))?
+        }
+        None => (retry.run(|| Task::toggle_with_id(id, &conn))?, true),
+    };
+    if changed {
+        hooks.notify(Event::Toggled, task.id, Some(&task), &conn);
+    }
+    TaskBody::new(task, params.shape(&prefer), &conn)
+}
+
+/// Creation is idempotent for todos carrying an `external_key`: when a task
+/// with that key already exists, it is returned with a 200 instead of a new
+/// task being created with a 201. New tasks come with the `Todo::warnings`
+/// about their todo, such as it being written in capitals.
+#[post("/todos?<params..>", format = "json", data = "<todo>")]
+#[allow(clippy::too_many_arguments)]
+fn create_task(
+    todo: ShallowJson<Todo>,
+    params: Form<MutationParams>,
+    prefer: Prefer,
+    _writable: Writable,
+    limits: State<Limits>,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Creation, task::Error> {
+    let shape = params.shape(&prefer);
+    let todo = todo.into_inner();
+    let (task, created) =
+        retry.run(|| Task::find_or_insert(todo.clone(), limits.max_description_len, &conn))?;
+    let warnings = todo.warnings();
+
+    if !created {
+        return Ok(Creation::Existing(TaskBody::new(task, shape, &conn)?));
+    }
+
+    hooks.notify(Event::Created, task.id, Some(&task), &conn);
+    Ok(Creation::Created(Created(
+        uri!("/api", get_task: id = task.id, links = _, touch = _).to_string(),
+        Some(TaskBody::new(task, shape, &conn)?.warn(warnings)),
+    )))
+}
+
+/// Creates up to `max_batch_size` tasks at once, or none of them when any is
+/// invalid. Todos with the `external_key` of an existing task are answered
+/// with that task, as with single creates.
+#[post("/todos/batch", format = "json", data = "<todos>")]
+fn create_tasks(
+    todos: ShallowJson<Vec<Todo>>,
+    _writable: Writable,
+    limits: State<Limits>,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Created<Json<Vec<Task>>>, RequestError> {
+    let todos = todos.into_inner();
+    if todos.len() > limits.max_batch_size {
+        return Err(RequestError::invalid(format!(
+            "At most {} tasks can be created at once.",
+            limits.max_batch_size
+        )));
+    }
+
+    let created =
+        retry.run(|| Task::insert_many(todos.clone(), limits.max_description_len, &conn))?;
+    for (task, _) in created.iter().filter(|(_, created)| *created) {
+        hooks.notify(Event::Created, task.id, Some(task), &conn);
+    }
+
+    Ok(Created(
+        String::from("/api/todos"),
+        Some(Json(created.into_iter().map(|(task, _)| task).collect())),
+    ))
+}
+
+/// Edits the fields present in the body, which may leave the task unchanged
+#[patch("/todos/<id>", format = "json", data = "<patch>")]
+fn update_task(
+    id: TaskId,
+    patch: ShallowJson<TaskPatch>,
+    _writable: Writable,
+    limits: State<Limits>,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Json<Task>, task::Error> {
+    let TaskId(id) = id;
+    let patch = patch.into_inner();
+    let task = retry.run(|| Task::update(id, patch.clone(), limits.max_description_len, &conn))?;
+    hooks.notify(Event::Updated, task.id, Some(&task), &conn);
+    Ok(Json(task))
+}
+
+/// Streams every task as a CSV file with `id`, `description` and `completed`
+/// columns, holding on to a database connection until it has all been sent.
+#[get("/todos/export.csv")]
+fn export_csv(conn: DbConn) -> Content<Stream<Export<DbConn>>> {
+    Content(ContentType::CSV, Stream::from(Export::new(conn)))
+}
+
+/// Imports the tasks of a CSV file with a `description` and an optional
+/// `completed` column, up to `import::MAX_ROWS` of them. Malformed rows, and those
+/// whose description is already taken, are skipped and reported, by line,
+/// alongside the number of tasks inserted.
+///
+/// The file is the whole body of the request, sent as `text/csv`, as with
+/// `curl --data-binary @tasks.csv -H 'Content-Type: text/csv'`.
+#[post("/todos/import.csv", format = "text/csv", data = "<upload>")]
+fn import_csv(
+    upload: Data,
+    _writable: Writable,
+    limits: State<Limits>,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Json<import::Summary>, Custom<Json<ApiError>>> {
+    let error =
+        |status: Status, message: String| Custom(status, ApiError::with_message(status, message));
+
+    let parsed = match import::parse(upload.open(), limits.max_description_len) {
+        Ok(parsed) => parsed,
+        Err(import::Error::TooLarge) => {
+            return Err(error(
+                Status::PayloadTooLarge,
+                format!("At most {} bytes can be imported at once.", import::LIMIT),
+            ))
+        }
+        Err(import::Error::TooManyRows) => {
+            return Err(error(
+                Status::PayloadTooLarge,
+                format!("At most {} rows can be imported at once.", import::MAX_ROWS),
+            ))
+        }
+        Err(import::Error::Csv(e)) => return Err(error(Status::BadRequest, e.to_string())),
+    };
+
+    let rows: Vec<_> = parsed
+        .rows
+        .iter()
+        .map(|(_, todo, completed)| (todo.clone(), *completed))
+        .collect();
+    let outcomes = retry
+        .run(|| Task::import(rows.clone(), &conn))
+        .map_err(|e| {
+            log::error!("Failed to import tasks: {:?}", e);
+            error(
+                Status::InternalServerError,
+                String::from("The tasks could not be saved."),
+            )
+        })?;
+
+    let (mut inserted, mut failed) = (0, parsed.failed);
+    let lines = parsed.rows.iter().map(|(line, _, _)| *line);
+    for (line, outcome) in lines.zip(outcomes) {
+        match outcome {
+            Ok(task) => {
+                inserted += 1;
+                hooks.notify(Event::Created, task.id, Some(&task), &conn);
+            }
+            Err(_) => failed.push(import::Failure {
+                line,
+                error: String::from("Another task already has this description."),
+            }),
+        }
+    }
+    failed.sort_by_key(|failure| failure.line);
+
+    Ok(Json(import::Summary { inserted, failed }))
+}
+
+/// Imports of any other type, such as the `multipart/form-data` of HTML forms,
+/// which aren't supported. Ranked after `import_csv`, which takes the
+/// `text/csv` ones.
+#[post("/todos/import.csv", rank = 1)]
+fn import_csv_unsupported() -> Custom<Json<ApiError>> {
+    Custom(
+        Status::UnsupportedMediaType,
+        ApiError::with_message(
+            Status::UnsupportedMediaType,
+            String::from(
+                "Send the CSV file as the body of the request, with `Content-Type: text/csv`; \
+                 multipart uploads aren't supported.",
+            ),
+        ),
+    )
+}
+
+#[delete("/todos/<id>?<params..>")]
+fn delete_task(
+    id: TaskId,
+    params: Form<MutationParams>,
+    _writable: Writable,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Deleted, task::Error> {
+    let TaskId(id) = id;
+    retry.run(|| Task::delete_with_id(id, &conn))?;
+    hooks.notify(Event::Deleted, id, None, &conn);
+
+    match params.shape {
+        Some(Return::List) => Ok(Deleted::List(Json(first_page(&conn)?))),
+        _ => Ok(Deleted::Nothing(())),
+    }
+}
+
+/// Clears the completed tasks, except locked ones, in one go
+#[delete("/todos/completed")]
+fn delete_completed(
+    _writable: Writable,
+    retry: State<RetryPolicy>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<JsonValue, task::Error> {
+    let (deleted, ids) = retry.run(|| Task::delete_completed(&conn).map_err(task::Error::from))?;
+    for &id in &ids {
+        hooks.notify(Event::Deleted, id, None, &conn);
+    }
+
+    Ok(json!({ "deleted": deleted }))
+}
+
+/// Locking, which only admins can do, protects a task from toggles and
+/// deletes until it is explicitly unlocked.
+#[post("/todos/<id>/lock")]
+fn lock_task(
+    id: TaskId,
+    _admin: Admin,
+    _writable: Writable,
+    retry: State<RetryPolicy>,
+    conn: DbConn,
+) -> Result<Json<Task>, task::Error> {
+    let TaskId(id) = id;
+    Ok(Json(retry.run(|| Task::set_locked(id, true, &conn))?))
+}
+
+#[post("/todos/<id>/unlock")]
+fn unlock_task(
+    id: TaskId,
+    _admin: Admin,
+    _writable: Writable,
+    retry: State<RetryPolicy>,
+    conn: DbConn,
+) -> Result<Json<Task>, task::Error> {
+    let TaskId(id) = id;
+    Ok(Json(retry.run(|| Task::set_locked(id, false, &conn))?))
+}
+
+/// Markdown preview, see `markdown::render` for what is supported
+#[post("/todos/render", format = "json", data = "<body>")]
+fn render_markdown(body: ShallowJson<Markdown>) -> JsonValue {
+    json!({ "html": markdown::render(&body.0.text) })
+}
+
+/// Webhooks
+#[get("/admin/webhooks")]
+fn get_webhooks(_admin: Admin, conn: DbConn) -> Result<Json<Vec<Webhook>>, Status> {
+    Webhook::all(&conn)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[post("/admin/webhooks", format = "json", data = "<hook>")]
+fn create_webhook(
+    _admin: Admin,
+    hook: ShallowJson<NewWebhook>,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Result<Json<Webhook>, Custom<Json<ApiError>>> {
+    let hook = hook.into_inner();
+    if let Err(reason) = hook.validate() {
+        return Err(Custom(
+            Status::UnprocessableEntity,
+            ApiError::with_message(Status::UnprocessableEntity, reason),
+        ));
+    }
+
+    let created = Webhook::insert(hook, &conn).map(Json).ok_or_else(|| {
+        Custom(
+            Status::InternalServerError,
+            ApiError::new(Status::InternalServerError),
+        )
+    });
+    hooks.reload();
+    created
+}
+
+#[delete("/admin/webhooks/<id>")]
+fn delete_webhook(
+    _admin: Admin,
+    id: i32,
+    hooks: State<Dispatcher>,
+    conn: DbConn,
+) -> Option<Custom<()>> {
+    if Webhook::delete_with_id(id, &conn) {
+        hooks.reload();
+        Some(Custom(Status::NoContent, ()))
+    } else {
+        None
+    }
+}
+
+/// Unsupported methods on task resources
+#[put("/todos")]
+fn tasks_put() -> NotAllowed {
+    NotAllowed(TASKS_ALLOW)
+}
+
+#[patch("/todos")]
+fn tasks_patch() -> NotAllowed {
+    NotAllowed(TASKS_ALLOW)
+}
+
+#[delete("/todos")]
+fn tasks_delete() -> NotAllowed {
+    NotAllowed(TASKS_ALLOW)
+}
+
+/// Only task ids get a 405: other paths under `/todos`, such as `/todos/count`,
+/// aren't tasks, and PUT, PATCH and DELETE find nothing there either
+#[post("/todos/<_id>", rank = 2)]
+fn task_post(_id: TaskId) -> NotAllowed {
+    NotAllowed(TASK_ALLOW)
+}
+
+/// Error catchers
+///
+/// Every error gets an `ApiError` body, rather than Rocket's HTML pages, with
+/// the reason for it when it's known.
+#[catch(400)]
+fn bad_request(_: &Request) -> Json<ApiError> {
+    ApiError::new(Status::BadRequest)
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request) -> Json<ApiError> {
+    match request.local_cache(|| Unauthorized(None)).0.clone() {
+        Some(reason) => ApiError::with_message(Status::Unauthorized, reason),
+        None => ApiError::new(Status::Unauthorized),
+    }
+}
+
+#[catch(404)]
+fn not_found(_: &Request) -> Json<ApiError> {
+    ApiError::new(Status::NotFound)
+}
+
+#[catch(405)]
+fn method_not_allowed(request: &Request) -> MethodNotAllowed {
+    let allow = request.local_cache(|| Allow(None)).0.unwrap_or("");
+
+    MethodNotAllowed {
+        error: ApiError::with_message(
+            Status::MethodNotAllowed,
+            format!("This resource only supports: {}.", allow),
+        ),
+        allow: Header::new("Allow", allow),
+    }
+}
+
+#[catch(422)]
+fn unprocessable_entity(request: &Request) -> Json<ApiError> {
+    match request.local_cache(|| Invalid(None)).0.clone() {
+        Some(reason) => ApiError::with_message(Status::UnprocessableEntity, reason),
+        None => ApiError::new(Status::UnprocessableEntity),
+    }
+}
+
+#[catch(423)]
+fn locked(_: &Request) -> Json<ApiError> {
+    ApiError::new(Status::Locked)
+}
+
+#[catch(429)]
+fn too_many_requests<'r>(request: &Request) -> response::Result<'r> {
+    let mut response = Custom(
+        Status::TooManyRequests,
+        ApiError::new(Status::TooManyRequests),
+    )
+    .respond_to(request)?;
+    if let Some(secs) = request.local_cache(|| RetryAfter(None)).0 {
+        response.set_raw_header("Retry-After", secs.to_string());
+    }
+
+    Ok(response)
+}
+
+#[catch(500)]
+fn internal_error(_: &Request) -> Json<ApiError> {
+    ApiError::new(Status::InternalServerError)
+}
+
+#[catch(503)]
+fn service_unavailable(request: &Request) -> Json<ApiError> {
+    match request.local_cache(|| Unavailable(None)).0.clone() {
+        Some(reason) => ApiError::with_message(Status::ServiceUnavailable, reason),
+        None => ApiError::new(Status::ServiceUnavailable),
+    }
+}
+
+/// Database migrations
+fn run_db_migrations(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let conn = DbConn::get_one(&rocket).expect("database connection");
+    match embedded_migrations::run(&*conn) {
+        Ok(()) => Ok(rocket),
+        Err(e) => {
+            eprintln!("Failed to run database migrations: {:?}", e);
+            Err(rocket)
+        }
+    }
+}
+
+/// JSON limits
+fn configure_json(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let max_depth = match rocket.config().get_int("json_max_depth") {
+        Ok(depth) if depth > 0 => depth as usize,
+        Err(ConfigError::Missing(_)) => json::DEFAULT_MAX_DEPTH,
+        _ => {
+            eprintln!("`json_max_depth` must be a positive integer");
+            return Err(rocket);
+        }
+    };
+
+    Ok(rocket.manage(MaxDepth(max_depth)))
+}
+
+fn configure_read_only(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let read_only = match rocket.config().get_bool("read_only") {
+        Ok(read_only) => read_only,
+        Err(ConfigError::Missing(_)) => false,
+        Err(_) => {
+            eprintln!("`read_only` must be a boolean");
+            return Err(rocket);
+        }
+    };
+
+    Ok(rocket.manage(ReadOnly(AtomicBool::new(read_only))))
+}
+
+fn configure_soft_limit(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let soft_limit = match rocket.config().get_int("list_warning_rows") {
+        Ok(rows) if rows > 0 && rows < MAX_PAGE_SIZE => rows as usize,
+        Err(ConfigError::Missing(_)) => DEFAULT_SOFT_LIMIT,
+        _ => {
+            eprintln!(
+                "`list_warning_rows` must be a positive integer below {}, the largest page",
+                MAX_PAGE_SIZE
+            );
+            return Err(rocket);
+        }
+    };
+
+    Ok(rocket.manage(SoftLimit(soft_limit)))
+}
+
+fn configure_limits(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let limit = |name: &str, default: usize| match rocket.config().get_int(name) {
+        Ok(limit) if limit > 0 => Ok(limit as usize),
+        Err(ConfigError::Missing(_)) => Ok(default),
+        _ => Err(format!("`{}` must be a positive integer", name)),
+    };
+    let limits = limit("max_description_len", task::DEFAULT_MAX_DESCRIPTION_LEN).and_then(
+        |max_description_len| {
+            Ok(Limits {
+                max_description_len,
+                max_batch_size: limit("max_batch_size", DEFAULT_MAX_BATCH_SIZE)?,
+            })
+        },
+    );
+
+    match limits {
+        Ok(limits) => Ok(rocket.manage(limits)),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(rocket)
+        }
+    }
+}
+
+/// Providers are tried in order: bearer tokens, API keys, then Basic
+/// credentials
+fn configure_auth(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let configured = Tokens::from_config(&rocket).and_then(|tokens| {
+        let keys = ApiKey::from_config(&rocket)?;
+        let users = BasicAuth::from_config(&rocket)?;
+        Ok((tokens, keys, users))
+    });
+    match configured {
+        Ok((tokens, keys, users)) => Ok(rocket
+            .manage(AuthProviders(vec![
+                Box::new(BearerToken(tokens.clone())),
+                Box::new(keys),
+                Box::new(users),
+            ]))
+            .manage(tokens)),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(rocket)
+        }
+    }
+}
+
+fn configure_login_limit(rocket: Rocket) -> Result<Rocket, Rocket> {
+    match LoginLimiter::from_config(&rocket) {
+        Ok(limiter) => Ok(rocket.manage(limiter)),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(rocket)
+        }
+    }
+}
+
+fn configure_retries(rocket: Rocket) -> Result<Rocket, Rocket> {
+    match RetryPolicy::from_config(&rocket) {
+        Ok(policy) => Ok(rocket.manage(policy)),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(rocket)
+        }
+    }
+}
+
+/// The file behind a SQLite database URL, if it isn't an in-memory database
+fn database_file(url: &str) -> Option<PathBuf> {
+    let path = url.trim_start_matches("file:");
+    if path.starts_with(":memory:") || path.contains("mode=memory") {
+        None
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
+fn record_deployment(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let (pool_size, database_file) = match database_config("tasks", rocket.config()) {
+        Ok(config) => (config.pool_size, database_file(config.url)),
+        Err(_) => (0, None),
+    };
+    let deployment = Deployment {
+        environment: rocket.config().environment.to_string(),
+        pool_size,
+        database_file,
+        started: Instant::now(),
+    };
+
+    Ok(rocket.manage(deployment))
+}
+
+/// Static files
+///
+/// The frontend's files are served from `STATIC_DIR`, after every route which
+/// matches. Other `GET`s outside of `/api` and `/admin` get its `index.html`,
+/// so that the frontend can route them itself.
+const STATIC_DIR: &str = "static";
+
+/// Prefixes of the paths which only the server routes, including the one
+/// `Maintenance` reroutes requests to
+const SERVER_PATHS: &[&str] = &["api", "admin", "__maintenance__"];
+
+/// A path for the frontend to route: one outside of the `SERVER_PATHS` which
+/// isn't one of its files. Other paths are forwarded to `StaticFiles`, which
+/// doesn't forward what it can't find.
+struct FrontendRoute;
+
+impl<'a, 'r> FromRequest<'a, 'r> for FrontendRoute {
+    type Error = !;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match request.get_segments::<PathBuf>(0) {
+            Some(Ok(ref path))
+                if !SERVER_PATHS.iter().any(|prefix| path.starts_with(prefix))
+                    && !Path::new(STATIC_DIR).join(path).is_file() =>
+            {
+                Outcome::Success(FrontendRoute)
+            }
+            _ => Outcome::Forward(()),
+        }
+    }
+}
+
+#[get("/<_path..>", rank = 9)]
+fn frontend(_path: PathBuf, _route: FrontendRoute) -> Option<NamedFile> {
+    NamedFile::open(Path::new(STATIC_DIR).join("index.html")).ok()
+}
+
+/// Rocket instance
+fn ignite_rocket() -> Rocket {
+    assemble(rocket::ignite(), LogSink)
+}
+
+/// Fairings run in the order they are attached, so those which record the
+/// outcome of requests come after those which change it, while the request
+/// timer comes before those which reroute requests
+fn assemble(rocket: Rocket, request_log: impl Sink) -> Rocket {
+    rocket
+        .attach(DbConn::fairing())
+        .attach(AdHoc::on_attach("Database Migrations", run_db_migrations))
+        .attach(AdHoc::on_attach("JSON Limits", configure_json))
+        .attach(AdHoc::on_attach("Read-only Mode", configure_read_only))
+        .attach(AdHoc::on_attach("List Soft Limit", configure_soft_limit))
+        .attach(AdHoc::on_attach("Request Limits", configure_limits))
+        .attach(AdHoc::on_attach("Database Retries", configure_retries))
+        .attach(AdHoc::on_attach("Diagnostics", record_deployment))
+        .attach(AdHoc::on_attach("Authentication", configure_auth))
+        .attach(AdHoc::on_attach("Login Rate Limit", configure_login_limit))
+        .manage(Dispatcher::start())
+        .manage(Outcomes::default())
+        .attach(request_log::start_timer())
+        .attach(Maintenance::new(SystemClock))
+        .attach(Cors)
+        .attach(RecordOutcomes)
+        .attach(RequestLog::new(request_log))
+        .attach(Template::fairing())
+        .mount(
+            "/",
+            routes![
+                hello,
+                person,
+                adult,
+                child,
+                health,
+                health_live,
+                limits,
+                admin_dashboard,
+                set_read_only,
+                diagnostics,
+                db_stats,
+                error_rate,
+                get_webhooks,
+                create_webhook,
+                delete_webhook,
+                user_dashboard,
+                unauthenticated_user,
+                login_page,
+                task_list,
+                set_theme,
+                login,
+                frontend
+            ],
+        )
+        .mount("/", StaticFiles::from(STATIC_DIR))
+        .mount(
+            "/api",
+            routes![
+                get_tasks,
+                get_counts,
+                get_duplicates,
+                search_tasks,
+                get_recent_views,
+                get_task_by_slug,
+                get_task,
+                render_markdown,
+                create_task,
+                create_tasks,
+                import_csv,
+                import_csv_unsupported,
+                export_csv,
+                toggle_task,
+                update_task,
+                delete_task,
+                delete_completed,
+                lock_task,
+                unlock_task,
+                tasks_put,
+                tasks_patch,
+                tasks_delete,
+                task_post
+            ],
+        )
+        .register(catchers![
+            bad_request,
+            unauthorized,
+            not_found,
+            method_not_allowed,
+            unprocessable_entity,
+            locked,
+            too_many_requests,
+            internal_error,
+            service_unavailable
+        ])
+}
+
+fn main() {
+    ignite_rocket().launch();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assemble,
+        maintenance::{Clock, Maintenance},
+        AuthProviders, BearerToken, LogSink, Tokens,
+    };
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use rocket::{
+        config::{Config, Environment, Value},
+        error::LaunchErrorKind,
+        http::{ContentType, Cookie, Header, Status},
+        local::{Client, LocalRequest},
+        Rocket,
+    };
+    use std::{
+        collections::HashMap,
+        env, fs,
+        io::{Read, Write},
+        net::TcpListener,
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration as StdDuration,
+    };
+
+    /// `jwt_secret` of the test instances
+    const TEST_SECRET: &str = "test-secret";
+
+    /// An `Authorization` header with a valid token for the role
+    fn bearer(role: &str) -> Header<'static> {
+        let token = Tokens::new(TEST_SECRET.as_bytes()).issue_now("test@example.com", role);
+        Header::new("Authorization", format!("Bearer {}", token))
+    }
+
+    /// A Rocket instance backed by a fresh, migrated database file
+    fn test_rocket() -> Rocket {
+        test_rocket_with(HashMap::new())
+    }
+
+    /// `test_rocket`, with extra configuration parameters
+    fn test_rocket_with(extras: HashMap<&str, Value>) -> Rocket {
+        test_rocket_with_database(extras, HashMap::new())
+    }
+
+    /// `test_rocket_with`, with extra parameters for the `tasks` database
+    fn test_rocket_with_database(
+        extras: HashMap<&str, Value>,
+        database: HashMap<&str, Value>,
+    ) -> Rocket {
+        assemble(rocket::custom(test_config(extras, database)), LogSink)
+    }
+
+    /// The configuration of a test instance, which doesn't log requests unless
+    /// `extras` sets a `request_log_level`
+    fn test_config(extras: HashMap<&str, Value>, mut database: HashMap<&str, Value>) -> Config {
+        static NEXT_DB: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "rocket-demo-{}-{}.sqlite",
+            process::id(),
+            NEXT_DB.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_file(&path);
+
+        database.insert("url", Value::from(path.to_str().unwrap()));
+        let mut databases = HashMap::new();
+        databases.insert("tasks", Value::from(database));
+
+        extras
+            .into_iter()
+            .fold(
+                Config::build(Environment::Development)
+                    .extra("jwt_secret", TEST_SECRET)
+                    .extra("request_log_level", "off"),
+                |config, (name, value)| config.extra(name, value),
+            )
+            .extra("databases", databases)
+            .finalize()
+            .unwrap()
+    }
+
+    fn create(client: &Client, description: &str) -> String {
+        client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"description":"{}"}}"#, description))
+            .dispatch()
+            .body_string()
+            .unwrap()
+    }
+
+    /// Ids of the tasks listed at `uri`, in order, from an array of tasks or
+    /// from the columnar format
+    fn ids(client: &Client, uri: &str) -> Vec<i64> {
+        let mut response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let id = |value: &serde_json::Value| value.as_i64().unwrap();
+        match body.get("ids") {
+            Some(ids) => ids.as_array().unwrap().iter().map(id).collect(),
+            None => body
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|task| id(&task["id"]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn hello() {
+        let rocket = test_rocket();
+        let client = Client::new(rocket).unwrap();
+
+        let mut response = client.get("/").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
         assert_eq!(response.body_string(), Some(String::from("Hello world!")));
     }
 
     #[test]
     fn person() {
-        let rocket = ignite_rocket();
+        let rocket = test_rocket();
         let client = Client::new(rocket).unwrap();
 
         let mut response = client.get("/hello/hugo/30").dispatch();
@@ -280,7 +1854,7 @@ mod tests {
 
     #[test]
     fn age() {
-        let rocket = ignite_rocket();
+        let rocket = test_rocket();
         let client = Client::new(rocket).unwrap();
 
         let mut response = client.get("/hello/30").dispatch();
@@ -304,13 +1878,9 @@ mod tests {
 
     #[test]
     fn auth() {
-        let rocket = ignite_rocket();
-        let client = Client::new(rocket).unwrap();
+        let client = Client::new(test_rocket()).unwrap();
 
-        let mut response = client
-            .get("/admin")
-            .header(Header::new("Authorization", "Bearer admin"))
-            .dispatch();
+        let mut response = client.get("/admin").header(bearer("admin")).dispatch();
 
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(
@@ -318,10 +1888,7 @@ mod tests {
             Some(String::from("Welcome, administrator!"))
         );
 
-        let mut response = client
-            .get("/admin")
-            .header(Header::new("Authorization", "Bearer user"))
-            .dispatch();
+        let mut response = client.get("/admin").header(bearer("user")).dispatch();
 
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(
@@ -334,4 +1901,2042 @@ mod tests {
         assert_eq!(response.status(), Status::SeeOther);
         assert_eq!(response.headers().get_one("Location"), Some("/login"));
     }
+
+    #[test]
+    fn login_rate_limit() {
+        let client = Client::new(test_rocket()).unwrap();
+        let login = |ip: &str| {
+            client
+                .post("/login")
+                .remote(format!("{}:4000", ip).parse().unwrap())
+                .header(ContentType::Form)
+                .body("email=ada@example.com&password=guess")
+                .dispatch()
+        };
+
+        for _ in 0..5 {
+            assert_eq!(login("192.0.2.1").status(), Status::Ok);
+        }
+        let mut response = login("192.0.2.1");
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+        assert!(response.body_string().unwrap().contains(r#""code":429"#));
+
+        // Other clients have their own allowance
+        assert_eq!(login("192.0.2.2").status(), Status::Ok);
+
+        // `X-Real-IP` doesn't name a client unless it comes from a trusted proxy
+        let mut extras = HashMap::new();
+        extras.insert(
+            "login_trusted_proxies",
+            Value::from(vec![Value::from("192.0.2.10")]),
+        );
+        let client = Client::new(test_rocket_with(extras)).unwrap();
+        let login = |remote: &str, real_ip: &str| {
+            client
+                .post("/login")
+                .remote(format!("{}:4000", remote).parse().unwrap())
+                .header(Header::new("X-Real-IP", real_ip.to_string()))
+                .header(ContentType::Form)
+                .body("email=ada@example.com&password=guess")
+                .dispatch()
+                .status()
+        };
+
+        for n in 0..5 {
+            assert_eq!(login("192.0.2.1", &format!("198.51.100.{}", n)), Status::Ok);
+        }
+        assert_eq!(login("192.0.2.1", "198.51.100.9"), Status::TooManyRequests);
+        for _ in 0..5 {
+            assert_eq!(login("192.0.2.10", "198.51.100.1"), Status::Ok);
+        }
+        assert_eq!(login("192.0.2.10", "198.51.100.1"), Status::TooManyRequests);
+        assert_eq!(login("192.0.2.10", "198.51.100.2"), Status::Ok);
+
+        // Requests from an unknown address aren't let through a shared allowance
+        let response = client
+            .post("/login")
+            .header(ContentType::Form)
+            .body("email=ada@example.com&password=guess")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn login_rate_limit_clients() {
+        use super::rate_limit::{LoginLimiter, MAX_CLIENTS};
+        use std::{net::IpAddr, time::Instant};
+
+        let limiter = LoginLimiter::new(5, StdDuration::from_secs(60), vec![]);
+        let start = Instant::now();
+        let ip = |n: usize| IpAddr::from([10, 0, (n >> 8) as u8, n as u8]);
+        for _ in 0..5 {
+            assert!(limiter.attempt(ip(0), start).is_ok());
+        }
+        assert!(limiter.attempt(ip(0), start).is_err());
+        for n in 1..MAX_CLIENTS {
+            assert!(limiter
+                .attempt(ip(n), start + StdDuration::from_secs(1))
+                .is_ok());
+        }
+
+        // New clients make room by evicting the oldest window rather than
+        // being turned away
+        let later = start + StdDuration::from_secs(15);
+        assert!(limiter.attempt(ip(MAX_CLIENTS), later).is_ok());
+        assert!(limiter.attempt(ip(1), later).is_ok());
+        assert!(limiter.attempt(ip(0), later).is_ok());
+    }
+
+    #[test]
+    fn auth_providers() {
+        let mut key = HashMap::new();
+        key.insert("key", Value::from("ci-key"));
+        key.insert("role", Value::from("admin"));
+        let mut keys = HashMap::new();
+        keys.insert("ci", Value::from(key));
+        let mut user = HashMap::new();
+        user.insert("password", Value::from("ops-password"));
+        user.insert("role", Value::from("admin"));
+        let mut users = HashMap::new();
+        users.insert("ops@example.com", Value::from(user));
+        let mut extras = HashMap::new();
+        extras.insert("api_keys", Value::from(keys));
+        extras.insert("basic_users", Value::from(users));
+        let client = Client::new(test_rocket_with(extras)).unwrap();
+        let admin = |headers: &[Header<'static>]| {
+            let mut request = client.get("/admin");
+            for header in headers {
+                request.add_header(header.clone());
+            }
+            let mut response = request.dispatch();
+            (
+                response.status(),
+                response.body_string().unwrap_or_default(),
+            )
+        };
+        let api_key = |key: &'static str| Header::new("X-API-Key", key);
+        let basic = |credentials: &str| {
+            Header::new(
+                "Authorization",
+                format!("Basic {}", base64::encode(credentials)),
+            )
+        };
+        let welcome_admin = (Status::Ok, String::from("Welcome, administrator!"));
+        let welcome_user = (Status::Ok, String::from("Welcome, simple user!"));
+
+        // Requests without credentials fall through every provider
+        assert_eq!(admin(&[]).0, Status::SeeOther);
+        assert_eq!(admin(&[api_key("ci-key")]), welcome_admin);
+        assert_eq!(
+            admin(&[basic("ops@example.com:ops-password")]),
+            welcome_admin
+        );
+
+        // Only the configured users are accepted, unlike at `POST /login`
+        for credentials in &[
+            "ops@example.com:admin",
+            "ada@example.com:admin",
+            "ada@example.com:ops-password",
+        ] {
+            let (status, body) = admin(&[basic(credentials)]);
+            assert_eq!(status, Status::Unauthorized);
+            assert!(body.contains("The Basic credentials are invalid."));
+        }
+
+        // The first provider which finds credentials decides
+        assert_eq!(admin(&[bearer("user"), api_key("ci-key")]), welcome_user);
+        let (status, body) = admin(&[api_key("guess"), basic("ops@example.com:ops-password")]);
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("The API key is invalid."));
+
+        let (status, body) = admin(&[Header::new("Authorization", "Basic !!!")]);
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("The Basic credentials are malformed."));
+    }
+
+    #[test]
+    fn tokens() {
+        let client = Client::new(test_rocket()).unwrap();
+        let admin = |token: &str| {
+            let mut response = client
+                .get("/admin")
+                .header(Header::new("Authorization", format!("Bearer {}", token)))
+                .dispatch();
+            (response.status(), response.body_string().unwrap())
+        };
+
+        let mut response = client
+            .post("/login")
+            .remote("192.0.2.1:4000".parse().unwrap())
+            .header(ContentType::Form)
+            .body("email=ada@example.com&password=admin")
+            .dispatch();
+        let body = response.body_string().unwrap();
+        let token = body
+            .trim_start_matches(r#"{"token":""#)
+            .trim_end_matches(r#""}"#);
+        assert_eq!(
+            admin(token),
+            (Status::Ok, String::from("Welcome, administrator!"))
+        );
+
+        let tokens = Tokens::new(TEST_SECRET.as_bytes());
+        let expired = tokens.issue(
+            "ada@example.com",
+            "admin",
+            Utc::now() - Duration::minutes(1),
+        );
+        let (status, body) = admin(&expired);
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("The token has expired."));
+
+        let forged = Tokens::new(b"another-secret").issue_now("ada@example.com", "admin");
+        let (status, body) = admin(&forged);
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("The token is invalid."));
+
+        // The claims of a user's token, with their role swapped for admin's
+        let user = tokens.issue_now("bob@example.com", "user");
+        let elevated = tokens.issue_now("bob@example.com", "admin");
+        let parts = |token: &str| token.split('.').map(String::from).collect::<Vec<_>>();
+        let tampered = [
+            parts(&user)[0].clone(),
+            parts(&elevated)[1].clone(),
+            parts(&user)[2].clone(),
+        ]
+        .join(".");
+        assert_eq!(admin(&tampered).0, Status::Unauthorized);
+
+        assert_eq!(admin("not-a-token").0, Status::Unauthorized);
+    }
+
+    #[test]
+    fn mutations_return_list() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let body = create(&client, "first");
+        assert!(body.starts_with(r#"{"id":1,"#));
+
+        let mut response = client
+            .post("/api/todos?return=list")
+            .header(ContentType::JSON)
+            .body(r#"{"description":"second"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Created);
+        let body = response.body_string().unwrap();
+        assert!(body.starts_with(r#"[{"id":2,"description":"second""#));
+        assert!(body.contains(r#""description":"first""#));
+
+        let mut response = client.put("/api/todos/1?return=list").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.body_string().unwrap().starts_with("[{"));
+
+        let response = client.delete("/api/todos/2").dispatch();
+
+        assert_eq!(response.status(), Status::NoContent);
+
+        let mut response = client.delete("/api/todos/1?return=list").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some(String::from("[]")));
+
+        // Lists are no longer than the first page of `GET /todos`
+        let todos = (0..=super::DEFAULT_PAGE_SIZE)
+            .map(|n| format!(r#"{{"description":"task {}"}}"#, n))
+            .collect::<Vec<_>>()
+            .join(",");
+        client
+            .post("/api/todos/batch")
+            .header(ContentType::JSON)
+            .body(format!("[{}]", todos))
+            .dispatch();
+        let mut response = client.put("/api/todos/3?return=list").dispatch();
+        let tasks: Vec<serde_json::Value> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(tasks.len() as i64, super::DEFAULT_PAGE_SIZE);
+        assert_eq!(tasks[0]["description"], "task 50");
+    }
+
+    #[test]
+    fn duplicates() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let mut response = client.get("/api/todos/duplicates").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string(), Some(String::from("[]")));
+
+        create(&client, "Buy milk");
+        create(&client, "Walk the dog");
+        create(&client, "  buy   MILK ");
+        create(&client, "walk  the  dog");
+        create(&client, "Buy  Milk");
+        client.put("/api/todos/4").dispatch();
+
+        let mut response = client.get("/api/todos/duplicates").dispatch();
+
+        assert_eq!(
+            response.body_string(),
+            Some(String::from(
+                r#"[{"description":"buy milk","ids":[1,3,5]}]"#
+            ))
+        );
+    }
+
+    #[test]
+    fn json_depth() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let nested = format!("{}{}", "[".repeat(33), "]".repeat(33));
+        let mut response = client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"description":"deep","extra":{}}}"#, nested))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response.body_string().unwrap().contains(r#""code":422"#));
+
+        let response = client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(r#"{"description":"[[[[ not nested, just text"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    #[test]
+    fn read_only() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "before");
+
+        let set_read_only = |on: &str| {
+            client
+                .post("/admin/readonly")
+                .header(ContentType::JSON)
+                .header(bearer("admin"))
+                .body(on)
+                .dispatch()
+                .status()
+        };
+
+        assert_eq!(set_read_only("true"), Status::Ok);
+
+        let mut response = client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(r#"{"description":"during"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert!(response.body_string().unwrap().contains("read-only"));
+        assert_eq!(
+            client.put("/api/todos/1").dispatch().status(),
+            Status::ServiceUnavailable
+        );
+        assert_eq!(
+            client.delete("/api/todos/1").dispatch().status(),
+            Status::ServiceUnavailable
+        );
+        assert_eq!(client.get("/api/todos/1").dispatch().status(), Status::Ok);
+
+        assert_eq!(set_read_only("false"), Status::Ok);
+        assert_eq!(
+            client.delete("/api/todos/1").dispatch().status(),
+            Status::NoContent
+        );
+    }
+
+    struct FixedClock(DateTime<Utc>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn maintenance_windows() {
+        let noon = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let client_at = |now: DateTime<Utc>| {
+            let mut window = HashMap::new();
+            window.insert("start", Value::from("2024-06-01T11:00:00Z"));
+            window.insert("end", Value::from("2024-06-01T13:00:00Z"));
+
+            let config = Config::build(Environment::Development)
+                .extra("maintenance_windows", vec![Value::from(window)])
+                .finalize()
+                .unwrap();
+            let rocket = rocket::custom(config)
+                .attach(Maintenance::new(FixedClock(now)))
+                .manage(AuthProviders(vec![Box::new(BearerToken(Tokens::new(
+                    TEST_SECRET.as_bytes(),
+                )))]))
+                .mount(
+                    "/",
+                    routes![
+                        super::hello,
+                        super::health_live,
+                        super::admin_dashboard,
+                        super::frontend
+                    ],
+                );
+
+            Client::new(rocket).unwrap()
+        };
+
+        let client = client_at(noon);
+        let mut response = client.get("/").dispatch();
+
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("3600"));
+        assert!(response.body_string().unwrap().contains(r#""code":503"#));
+
+        // Frontend paths are rerouted too, rather than served the index
+        let mut response = client.get("/some/page").dispatch();
+
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert!(response.body_string().unwrap().contains("maintenance"));
+
+        let response = client.get("/admin").header(bearer("admin")).dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/health/live").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+
+        let client = client_at(noon + Duration::hours(2));
+        let response = client.get("/").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/__maintenance__").dispatch();
+
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn cors() {
+        let mut origins = HashMap::new();
+        origins.insert(
+            "cors_allowed_origins",
+            Value::from(vec![Value::from("https://todo.example.com")]),
+        );
+        let client = Client::new(test_rocket_with(origins)).unwrap();
+        let origin = Header::new("Origin", "https://todo.example.com");
+
+        let response = client
+            .options("/api/todos")
+            .header(origin.clone())
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .header(Header::new(
+                "Access-Control-Request-Headers",
+                "Content-Type",
+            ))
+            .dispatch();
+        let headers = response.headers();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some("https://todo.example.com")
+        );
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Methods"),
+            Some("GET, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+        assert!(headers
+            .get_one("Access-Control-Allow-Headers")
+            .unwrap()
+            .contains("Content-Type"));
+
+        let response = client.get("/api/todos").header(origin).dispatch();
+        let headers = response.headers();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some("https://todo.example.com")
+        );
+        assert!(headers.contains("Access-Control-Allow-Methods"));
+        assert!(headers.contains("Access-Control-Allow-Headers"));
+        assert_eq!(headers.get_one("Vary"), Some("Origin"));
+
+        let response = client
+            .get("/api/todos")
+            .header(Header::new("Origin", "https://evil.example.com"))
+            .dispatch();
+        assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+
+        let response = client
+            .options("/api/todos")
+            .header(Header::new("Origin", "https://evil.example.com"))
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn health() {
+        use diesel::RunQueryDsl;
+
+        let client = Client::new(test_rocket()).unwrap();
+        let mut response = client.get("/health").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string().unwrap(), r#"{"status":"ok"}"#);
+
+        // Tasks going missing doesn't make the database unreachable
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query("DROP TABLE tasks")
+            .execute(&*conn)
+            .unwrap();
+        assert_eq!(client.get("/health").dispatch().status(), Status::Ok);
+
+        let mut response = client.get("/health/live").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string().unwrap(), r#"{"status":"ok"}"#);
+    }
+
+    #[test]
+    fn task_ids() {
+        use diesel::RunQueryDsl;
+
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "Buy milk");
+        assert_eq!(client.get("/api/todos/1").dispatch().status(), Status::Ok);
+
+        // Without a table, reaching the handlers would fail with a 500
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query("DROP TABLE tasks")
+            .execute(&*conn)
+            .unwrap();
+        for path in &["/api/todos/0", "/api/todos/-1"] {
+            assert_eq!(client.get(*path).dispatch().status(), Status::NotFound);
+            assert_eq!(client.put(*path).dispatch().status(), Status::NotFound);
+            assert_eq!(client.delete(*path).dispatch().status(), Status::NotFound);
+            let response = client
+                .patch(*path)
+                .header(ContentType::JSON)
+                .body(r#"{"description": "Buy bread"}"#)
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            for action in &["lock", "unlock"] {
+                let response = client
+                    .post(format!("{}/{}", path, action))
+                    .header(bearer("admin"))
+                    .dispatch();
+                assert_eq!(response.status(), Status::NotFound);
+            }
+        }
+        assert_eq!(
+            client.get("/api/todos/1").dispatch().status(),
+            Status::InternalServerError
+        );
+    }
+
+    #[test]
+    fn task_list() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "Buy <milk>");
+
+        let mut response = client
+            .get("/todos")
+            .header(Header::new("Accept", "application/json"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept"));
+        let tasks: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(tasks[0]["description"], "Buy <milk>");
+
+        let mut response = client
+            .get("/todos")
+            .header(Header::new(
+                "Accept",
+                "text/html,application/xhtml+xml,*/*;q=0.8",
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let body = response.body_string().unwrap();
+        assert!(body.contains("<li>Buy &lt;milk&gt;</li>"));
+
+        // Clients which don't say get JSON
+        let response = client.get("/todos").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+    }
+
+    #[test]
+    fn pool_timeout() {
+        let mut database = HashMap::new();
+        database.insert("pool_size", Value::from(1));
+        database.insert("connect_timeout_ms", Value::from(100));
+        let client = Client::new(test_rocket_with_database(HashMap::new(), database)).unwrap();
+
+        let held = super::DbConn::get_one(client.rocket()).unwrap();
+        let started = super::Instant::now();
+        let mut response = client.get("/api/todos").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("No database connection became available in time"));
+        assert!(started.elapsed() < StdDuration::from_secs(5));
+        assert_eq!(
+            client.get("/health").dispatch().status(),
+            Status::ServiceUnavailable
+        );
+
+        drop(held);
+        assert_eq!(client.get("/api/todos").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn request_log() {
+        use super::request_log::{RequestLog, Sink};
+        use log::Level;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<(Level, String)>>>);
+
+        impl Sink for Capture {
+            fn log(&self, level: Level, line: &str) {
+                self.0.lock().unwrap().push((level, line.to_string()));
+            }
+        }
+
+        let client_with = |level: &str, capture: &Capture| {
+            let config = Config::build(Environment::Development)
+                .extra("request_log_level", level)
+                .finalize()
+                .unwrap();
+            let rocket = rocket::custom(config)
+                .attach(RequestLog::new(capture.clone()))
+                .mount("/", routes![super::hello]);
+            Client::new(rocket).unwrap()
+        };
+
+        let capture = Capture::default();
+        let client = client_with("debug", &capture);
+        client.get("/").dispatch();
+        client.get("/missing?q=1").dispatch();
+
+        let lines = capture.0.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0, Level::Debug);
+        assert!(lines[0]
+            .1
+            .starts_with("method=GET path=/ status=200 duration_ms="));
+        assert!(lines[1]
+            .1
+            .starts_with("method=GET path=/missing status=404 duration_ms="));
+
+        let capture = Capture::default();
+        client_with("off", &capture).get("/").dispatch();
+        assert!(capture.0.lock().unwrap().is_empty());
+
+        // The whole app logs the status sent, once maintenance windows and CORS
+        // have had their say
+        let app_with = |name: &'static str, value: Value, capture: &Capture| {
+            let mut extras = HashMap::new();
+            extras.insert("request_log_level", Value::from("info"));
+            extras.insert(name, value);
+            let config = test_config(extras, HashMap::new());
+            Client::new(assemble(rocket::custom(config), capture.clone())).unwrap()
+        };
+
+        let mut window = HashMap::new();
+        window.insert("start", Value::from("2000-01-01T00:00:00Z"));
+        window.insert("end", Value::from("2999-01-01T00:00:00Z"));
+        let capture = Capture::default();
+        let windows = Value::from(vec![Value::from(window)]);
+        app_with("maintenance_windows", windows, &capture)
+            .get("/")
+            .dispatch();
+
+        let origins = Value::from(vec![Value::from("https://todo.example.com")]);
+        app_with("cors_allowed_origins", origins, &capture)
+            .options("/api/todos")
+            .header(Header::new("Origin", "https://todo.example.com"))
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .dispatch();
+
+        let lines = capture.0.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]
+            .1
+            .starts_with("method=GET path=/ status=503 duration_ms="));
+        assert!(lines[1]
+            .1
+            .starts_with("method=OPTIONS path=/api/todos status=204 duration_ms="));
+    }
+
+    #[test]
+    fn frontend() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let mut response = client.get("/style.css").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::CSS));
+        assert!(response.body_string().unwrap().contains("font-family"));
+
+        for uri in &["/tasks/12/edit", "/index.html"] {
+            let mut response = client.get(*uri).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.content_type(), Some(ContentType::HTML));
+            assert!(response
+                .body_string()
+                .unwrap()
+                .contains(r#"<div id="app">"#));
+        }
+
+        assert_eq!(
+            client.get("/").dispatch().body_string(),
+            Some(String::from("Hello world!"))
+        );
+
+        for uri in &["/api/nothing/here", "/api/todos/abc"] {
+            let mut response = client.get(*uri).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            assert!(response.body_string().unwrap().contains(r#""code":404"#));
+        }
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let mut response = client.post("/api/todos/1").dispatch();
+
+        assert_eq!(response.status(), Status::MethodNotAllowed);
+        assert_eq!(
+            response.headers().get_one("Allow"),
+            Some("GET, PUT, PATCH, DELETE")
+        );
+        assert!(response.body_string().unwrap().contains(r#""code":405"#));
+
+        // Paths which aren't tasks don't support any other method either
+        for uri in &["/api/todos/count", "/api/todos/search", "/api/todos/0"] {
+            let response = client.post(*uri).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(response.headers().get_one("Allow"), None);
+        }
+
+        let response = client.delete("/api/todos").dispatch();
+
+        assert_eq!(response.status(), Status::MethodNotAllowed);
+        assert_eq!(response.headers().get_one("Allow"), Some("GET, POST"));
+    }
+
+    #[test]
+    fn webhooks() {
+        let client = Client::new(test_rocket()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        // Nothing listens on the port of a listener once it's dropped
+        let unreachable = format!(
+            "http://{}/hook",
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        );
+        let accept = || {
+            let started = super::Instant::now();
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => return stream,
+                    Err(_) if started.elapsed() < StdDuration::from_secs(5) => {
+                        std::thread::sleep(StdDuration::from_millis(10))
+                    }
+                    Err(e) => panic!("no delivery in time: {}", e),
+                }
+            }
+        };
+
+        let register = |body: String| {
+            client
+                .post("/admin/webhooks")
+                .header(ContentType::JSON)
+                .header(bearer("admin"))
+                .body(body)
+                .dispatch()
+        };
+
+        let mut invalid = register(String::from(
+            r#"{"url":"https://example.com","events":"created"}"#,
+        ));
+        assert_eq!(invalid.status(), Status::UnprocessableEntity);
+        assert!(invalid
+            .body_string()
+            .unwrap()
+            .contains("`https://` is not supported yet."));
+
+        // A webhook which is down doesn't hold up deliveries to the others
+        let response = register(format!(r#"{{"url":"{}","events":"created"}}"#, unreachable));
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = register(format!(r#"{{"url":"{}","events":"created"}}"#, url));
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.body_string().unwrap().starts_with(r#"{"id":2,"#));
+
+        let started = super::Instant::now();
+        create(&client, "notify me");
+
+        let mut stream = accept();
+        assert!(started.elapsed() < StdDuration::from_secs(1));
+        stream.set_nonblocking(false).unwrap();
+        stream
+            .set_read_timeout(Some(StdDuration::from_secs(5)))
+            .unwrap();
+        let mut request = vec![0; 4096];
+        let read = stream.read(&mut request).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        let request = String::from_utf8_lossy(&request[..read]);
+        assert!(request.starts_with("POST /hook HTTP/1.1"));
+        assert!(request.contains(r#""event":"created""#));
+
+        for id in 1..=2 {
+            let response = client
+                .delete(format!("/admin/webhooks/{}", id))
+                .header(bearer("admin"))
+                .dispatch();
+            assert_eq!(response.status(), Status::NoContent);
+        }
+
+        let mut response = client
+            .get("/admin/webhooks")
+            .header(bearer("admin"))
+            .dispatch();
+        assert_eq!(response.body_string(), Some(String::from("[]")));
+    }
+
+    #[test]
+    fn slugs() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        assert!(create(&client, "Buy milk!").contains(r#""slug":"buy-milk","#));
+        assert!(create(&client, "buy  MILK").contains(r#""slug":"buy-milk-2","#));
+        assert!(create(&client, "Buy milk 2").contains(r#""slug":"buy-milk-2-2","#));
+        assert!(create(&client, "???").contains(r#""slug":"task","#));
+
+        let mut response = client.get("/api/todos/slug/buy-milk-2").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.body_string().unwrap().starts_with(r#"{"id":2,"#));
+
+        let response = client.get("/api/todos/slug/sell-milk").dispatch();
+
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn external_keys() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let seed = |description: &str| {
+            let mut response = client
+                .post("/api/todos")
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{"description":"{}","external_key":"seed-1"}}"#,
+                    description
+                ))
+                .dispatch();
+            (response.status(), response.body_string().unwrap())
+        };
+
+        let (status, body) = seed("Seeded");
+        assert_eq!(status, Status::Created);
+        assert!(body.starts_with(r#"{"id":1,"description":"Seeded""#));
+
+        let (status, body) = seed("Seeded again");
+        assert_eq!(status, Status::Ok);
+        assert!(body.starts_with(r#"{"id":1,"description":"Seeded""#));
+
+        assert!(create(&client, "No key").starts_with(r#"{"id":2,"#));
+        assert!(create(&client, "No key either").starts_with(r#"{"id":3,"#));
+    }
+
+    #[test]
+    fn render_markdown() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let render = |text: &str| {
+            client
+                .post("/api/todos/render")
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"text":{:?}}}"#, text))
+                .dispatch()
+                .body_string()
+                .unwrap()
+        };
+
+        assert_eq!(
+            render("**Buy** [milk](https://example.com)"),
+            r#"{"html":"<p><strong>Buy</strong> <a href=\"https://example.com\">milk</a></p>\n"}"#
+        );
+
+        let html = render("<script>alert(1)</script>\n\n<img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script") && !html.contains("<img"));
+        assert!(html.contains("&lt;script&gt;"));
+
+        let html = render("[a](javascript:alert(1)) [b](<JavaScript :alert(1)>) ![c](data:x)");
+        assert!(!html.to_lowercase().contains("javascript"));
+        assert!(!html.contains("data:"));
+        assert!(html.contains(r##"<a href=\"#\">a</a>"##));
+    }
+
+    #[test]
+    fn locks() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "final");
+
+        let admin = bearer("admin");
+        let user = bearer("user");
+
+        let response = client.post("/api/todos/1/lock").header(user).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let mut response = client
+            .post("/api/todos/1/lock")
+            .header(admin.clone())
+            .dispatch();
+        assert!(response.body_string().unwrap().contains(r#""locked":true"#));
+
+        let mut response = client.put("/api/todos/1").dispatch();
+        assert_eq!(response.status(), Status::Locked);
+        assert!(response.body_string().unwrap().contains(r#""code":423"#));
+        assert_eq!(
+            client.delete("/api/todos/1").dispatch().status(),
+            Status::Locked
+        );
+
+        let response = client.post("/api/todos/1/unlock").header(admin).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(client.put("/api/todos/1").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn links() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "final");
+
+        let mut response = client.get("/api/todos/1").dispatch();
+        assert!(!response.body_string().unwrap().contains("_links"));
+
+        let mut response = client.get("/api/todos/1?links=true").dispatch();
+        let body = response.body_string().unwrap();
+        assert!(body.contains(r#""description":"final""#));
+        assert!(body.contains(r#""self":{"href":"/api/todos/1","method":"GET"}"#));
+        assert!(body.contains(r#""toggle":{"href":"/api/todos/1","method":"PUT"}"#));
+        assert!(body.contains(r#""delete":{"href":"/api/todos/1","method":"DELETE"}"#));
+
+        let mut response = client.get("/api/todos?links=true").dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains(r#""self":{"href":"/api/todos/1","method":"GET"}"#));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let response = client
+            .get("/admin/diagnostics")
+            .header(bearer("user"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let mut response = client
+            .get("/admin/diagnostics")
+            .header(bearer("admin"))
+            .dispatch();
+        let body = response.body_string().unwrap();
+        assert!(body.contains(r#""database":"sqlite""#));
+        assert!(body.contains(r#""read_only":false"#));
+        assert!(body.contains(r#""json_max_depth":32"#));
+        assert!(!body.contains("rocket-demo-"));
+    }
+
+    #[test]
+    fn columnar() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "first");
+        create(&client, "second");
+        client.put("/api/todos/2").dispatch();
+
+        let mut response = client.get("/api/todos?format=columnar").dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"ids":[2,1],"descriptions":["second","first"],"completed":[true,false]}"#
+        );
+
+        let mut response = client.get("/api/todos?format=rows").dispatch();
+        assert!(response.body_string().unwrap().starts_with(r#"[{"id":2,"#));
+    }
+
+    #[test]
+    fn return_minimal() {
+        let client = Client::new(test_rocket()).unwrap();
+
+        let mut response = client
+            .post("/api/todos?return=minimal")
+            .header(ContentType::JSON)
+            .body(r#"{ "description": "final" }"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.body_string().unwrap(), r#"{"id":1}"#);
+
+        let prefer = Header::new("Prefer", "respond-async, return=minimal");
+        let mut response = client.put("/api/todos/1").header(prefer.clone()).dispatch();
+        assert_eq!(response.body_string().unwrap(), r#"{"id":1}"#);
+
+        let mut response = client
+            .put("/api/todos/1?return=representation")
+            .header(prefer)
+            .dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains(r#""description":"final""#));
+    }
+
+    #[test]
+    fn retries() {
+        use super::retry::{RetryPolicy, Transient};
+        use std::{cell::RefCell, time::Duration};
+
+        #[derive(Debug, PartialEq)]
+        enum Error {
+            Busy,
+            NotFound,
+        }
+
+        impl Transient for Error {
+            fn is_transient(&self) -> bool {
+                *self == Error::Busy
+            }
+        }
+
+        let ms = Duration::from_millis;
+        assert_eq!(RetryPolicy::delay(0, 0.0), ms(5));
+        assert_eq!(RetryPolicy::delay(2, 0.0), ms(20));
+        assert_eq!(RetryPolicy::delay(2, 0.5), ms(30));
+
+        // A fake clock, which only records the pauses it is asked for
+        let slept = RefCell::new(vec![]);
+        let sleep = |delay| slept.borrow_mut().push(delay);
+
+        let policy = RetryPolicy::new(4, ms(1000));
+        let mut failures = 3;
+        let result = policy.run_with(
+            sleep,
+            || 0.0,
+            || match failures {
+                0 => Ok(()),
+                _ => {
+                    failures -= 1;
+                    Err(Error::Busy)
+                }
+            },
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(*slept.borrow(), vec![ms(5), ms(10), ms(20)]);
+        assert_eq!(policy.stats().retries, 3);
+        assert_eq!(policy.stats().recovered, 1);
+
+        slept.borrow_mut().clear();
+        let mut attempts = 0;
+        let result: Result<(), _> = policy.run_with(
+            sleep,
+            || 0.0,
+            || {
+                attempts += 1;
+                Err(Error::NotFound)
+            },
+        );
+        assert_eq!(result, Err(Error::NotFound));
+        assert_eq!(attempts, 1);
+
+        // Out of attempts, then out of time to back off
+        for &(max_attempts, max_backoff, retries) in &[(2, ms(1000), 1), (4, ms(16), 2)] {
+            slept.borrow_mut().clear();
+            let policy = RetryPolicy::new(max_attempts, max_backoff);
+            let result: Result<(), _> = policy.run_with(sleep, || 0.0, || Err(Error::Busy));
+            assert_eq!(result, Err(Error::Busy));
+            assert_eq!(slept.borrow().len(), retries);
+        }
+    }
+
+    #[test]
+    fn locked_database() {
+        use super::{
+            retry::{RetryPolicy, Transient},
+            task::{Task, Todo},
+        };
+        use diesel::RunQueryDsl;
+
+        let mut extras = HashMap::new();
+        extras.insert("db_retry_attempts", Value::from(20));
+        extras.insert("db_retry_max_ms", Value::from(5000));
+        // Room for the request's connection, next to the two held here
+        let mut database = HashMap::new();
+        database.insert("pool_size", Value::from(3));
+        let client = Client::new(test_rocket_with_database(extras, database)).unwrap();
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        let todo = |description: &str| Todo {
+            description: description.to_string(),
+            external_key: None,
+        };
+
+        let locker = super::DbConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query("BEGIN IMMEDIATE")
+            .execute(&*locker)
+            .unwrap();
+        assert!(Task::insert(todo("first"), &conn)
+            .unwrap_err()
+            .is_transient());
+
+        // Imports are retried until the lock is released
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(StdDuration::from_millis(50));
+            diesel::sql_query("COMMIT").execute(&*locker).unwrap();
+        });
+        let response = client
+            .post("/api/todos/import.csv")
+            .header(ContentType::new("text", "csv"))
+            .body("description\nfirst\n")
+            .dispatch();
+        release.join().unwrap();
+        assert_eq!(response.status(), Status::Ok);
+        let retry = client.rocket().state::<RetryPolicy>().unwrap();
+        assert_eq!(retry.stats().recovered, 1);
+
+        // Other errors aren't worth retrying
+        assert!(!Task::insert(todo("first"), &conn)
+            .unwrap_err()
+            .is_transient());
+    }
+
+    #[test]
+    fn import_csv() {
+        let client = Client::new(test_rocket()).unwrap();
+        let csv = ContentType::new("text", "csv");
+
+        let mut response = client
+            .post("/api/todos/import.csv")
+            .header(csv.clone())
+            .body("description,completed\nfirst,\n\"second, quoted\",true\n,false\nthird,maybe\n")
+            .dispatch();
+        let body = response.body_string().unwrap();
+        assert!(body.starts_with(r#"{"inserted":2,"failed":[{"line":4,"#));
+        assert!(body.contains(r#"{"line":5,"#));
+
+        let mut response = client.get("/api/todos/2").dispatch();
+        let body = response.body_string().unwrap();
+        assert!(body.contains(r#""description":"second, quoted","completed":true"#));
+
+        let rows = "description\n".to_string() + &"task\n".repeat(super::import::MAX_ROWS + 1);
+        let response = client
+            .post("/api/todos/import.csv")
+            .header(csv.clone())
+            .body(rows)
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(
+            client.get("/api/todos/3").dispatch().status(),
+            Status::NotFound
+        );
+
+        // Files over the limit are rejected as a whole, not cut short
+        let padding = "x".repeat(2048);
+        let rows = "description,padding\n".to_string()
+            + &format!("task,{}\n", padding).repeat(super::import::MAX_ROWS - 100);
+        assert!(rows.len() as u64 > super::import::LIMIT);
+        let mut response = client
+            .post("/api/todos/import.csv")
+            .header(csv)
+            .body(rows)
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert!(response.body_string().unwrap().contains("bytes"));
+        assert_eq!(
+            client.get("/api/todos/3").dispatch().status(),
+            Status::NotFound
+        );
+
+        let mut response = client
+            .post("/api/todos/import.csv")
+            .header(Header::new("Content-Type", "multipart/form-data; boundary=X"))
+            .body("--X\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\ndescription\r\n--X--\r\n")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("`Content-Type: text/csv`"));
+    }
+
+    #[test]
+    fn export_csv() {
+        use super::{
+            export::BATCH_SIZE,
+            task::{Task, Todo},
+        };
+
+        let client = Client::new(test_rocket()).unwrap();
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        let rows = (0..=BATCH_SIZE * 6)
+            .map(|n| {
+                let todo = Todo {
+                    description: format!("task {}", n),
+                    external_key: None,
+                };
+                (todo, n % 2 == 0)
+            })
+            .collect();
+        Task::import(rows, &conn).unwrap();
+
+        let mut response = client.get("/api/todos/export.csv").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        let body = response.body_string().unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len() as i64, BATCH_SIZE * 6 + 2);
+        assert_eq!(lines[0], "id,description,completed");
+        assert_eq!(lines[1], "1,task 0,true");
+        assert_eq!(lines[lines.len() - 1], "3001,task 3000,true");
+
+        let client = Client::new(test_rocket()).unwrap();
+        let mut response = client.get("/api/todos/export.csv").dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            "id,description,completed\n"
+        );
+    }
+
+    #[test]
+    fn soft_limit() {
+        let mut extras = HashMap::new();
+        extras.insert("list_warning_rows", Value::from(2));
+        let client = Client::new(test_rocket_with(extras)).unwrap();
+
+        create(&client, "first");
+        create(&client, "second");
+        let response = client.get("/api/todos").dispatch();
+        assert_eq!(response.headers().get_one("Warning"), None);
+
+        create(&client, "third");
+        let mut response = client.get("/api/todos?format=columnar").dispatch();
+        assert_eq!(
+            response.headers().get_one("Warning"),
+            Some(r#"299 - "3 tasks returned, above the soft limit of 2; consider paginating""#)
+        );
+        assert!(response.body_string().unwrap().contains(r#""ids":[3,2,1]"#));
+
+        // Lists can only grow past the default soft limit on larger pages
+        let client = Client::new(test_rocket()).unwrap();
+        let rows = (0..=super::DEFAULT_SOFT_LIMIT)
+            .fold(String::from("description\n"), |rows, n| {
+                rows + &format!("task {}\n", n)
+            });
+        client
+            .post("/api/todos/import.csv")
+            .header(ContentType::new("text", "csv"))
+            .body(rows)
+            .dispatch();
+        let response = client.get("/api/todos").dispatch();
+        assert_eq!(response.headers().get_one("Warning"), None);
+        let response = client.get("/api/todos?limit=200").dispatch();
+        assert!(response.headers().get_one("Warning").is_some());
+
+        // Soft limits lists can't reach are rejected
+        let mut extras = HashMap::new();
+        extras.insert("list_warning_rows", Value::from(super::MAX_PAGE_SIZE));
+        match Client::new(test_rocket_with(extras)) {
+            Err(e) => match e.kind() {
+                LaunchErrorKind::FailedFairings(_) => {}
+                kind => panic!("unexpected launch error: {}", kind),
+            },
+            Ok(_) => panic!("launched with an unreachable soft limit"),
+        }
+    }
+
+    #[test]
+    fn database_errors() {
+        use diesel::RunQueryDsl;
+
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "final");
+
+        // Break the schema under the running application
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query("DROP TABLE tasks")
+            .execute(&*conn)
+            .unwrap();
+
+        for request in vec![
+            client.get("/api/todos"),
+            client.get("/api/todos/1"),
+            client.get("/api/todos/slug/final"),
+            client.get("/api/todos/duplicates"),
+            client.put("/api/todos/1"),
+            client.delete("/api/todos/1"),
+        ] {
+            let mut response = request.dispatch();
+            assert_eq!(response.status(), Status::InternalServerError);
+            assert!(response.body_string().unwrap().contains(r#""code":500"#));
+        }
+    }
+
+    #[test]
+    fn error_bodies() {
+        use diesel::RunQueryDsl;
+        use serde_json::Value as Json;
+
+        let client = Client::new(test_rocket()).unwrap();
+        let error = |mut response: rocket::local::LocalResponse| {
+            assert_eq!(response.content_type(), Some(ContentType::JSON));
+            let body = response.body_string().unwrap();
+            let error: Json = serde_json::from_str(&body).unwrap();
+            let fields = error.as_object().unwrap();
+            assert_eq!(fields.len(), 3);
+            assert_eq!(
+                fields["code"].as_u64(),
+                Some(u64::from(response.status().code))
+            );
+            assert_eq!(fields["name"], response.status().reason);
+            assert!(fields["message"].is_string());
+        };
+
+        let response = client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(r#"{ "description": "#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        error(response);
+
+        let response = client.get("/api/todos/404").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        error(response);
+
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        diesel::sql_query("DROP TABLE webhooks")
+            .execute(&*conn)
+            .unwrap();
+        let response = client
+            .get("/admin/webhooks")
+            .header(bearer("admin"))
+            .dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+        error(response);
+    }
+
+    #[test]
+    fn pagination() {
+        let client = Client::new(test_rocket()).unwrap();
+        let rows = (0..60).fold(String::from("description\n"), |rows, n| {
+            rows + &format!("task {}\n", n)
+        });
+        client
+            .post("/api/todos/import.csv")
+            .header(ContentType::new("text", "csv"))
+            .body(rows)
+            .dispatch();
+
+        let default = ids(&client, "/api/todos");
+        assert_eq!(default.len(), 50);
+        assert_eq!((default[0], default[49]), (60, 11));
+        assert_eq!(ids(&client, "/api/todos?limit=3&offset=10"), [50, 49, 48]);
+        let response = client.get("/api/todos?limit=3").dispatch();
+        assert_eq!(response.headers().get_one("X-Total-Count"), Some("60"));
+
+        let mut response = client.get("/api/todos?limit=201").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().unwrap().contains(r#""code":400"#));
+
+        for uri in &["/api/todos?limit=abc", "/api/todos?offset=1.5"] {
+            let mut response = client.get(*uri).dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+            assert!(response
+                .body_string()
+                .unwrap()
+                .contains("`limit` and `offset` must be integers"));
+        }
+    }
+
+    #[test]
+    fn theme() {
+        let client = Client::new(test_rocket()).unwrap();
+        let login = |theme: &str| {
+            client
+                .get("/login")
+                .cookie(Cookie::new("theme", theme.to_string()))
+                .dispatch()
+                .body_string()
+                .unwrap()
+        };
+
+        let response = client
+            .post("/theme")
+            .header(ContentType::Form)
+            .body("theme=dark")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(response
+            .headers()
+            .get_one("Set-Cookie")
+            .unwrap()
+            .starts_with("theme=dark"));
+
+        assert!(login("dark").contains("<style>"));
+        assert!(login("light").contains("<h1>Login</h1>"));
+        assert!(!login("light").contains("<style>"));
+        assert!(!login("neon").contains("<style>"));
+
+        let response = client
+            .post("/theme")
+            .header(ContentType::Form)
+            .body("theme=neon")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn db_stats() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "final");
+
+        let mut response = client
+            .get("/admin/db-stats")
+            .header(bearer("admin"))
+            .dispatch();
+        let body = response.body_string().unwrap();
+        assert!(body.starts_with(r#"{"tables":{"tasks":1,"webhooks":0},"file_size":"#));
+
+        assert_eq!(super::database_file(":memory:"), None);
+        assert_eq!(super::database_file("file::memory:?cache=shared"), None);
+        assert_eq!(
+            super::database_file("db/tasks.sqlite"),
+            Some("db/tasks.sqlite".into())
+        );
+    }
+
+    #[test]
+    fn completed_filter() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "open");
+        create(&client, "done");
+        client.put("/api/todos/2").dispatch();
+
+        assert_eq!(ids(&client, "/api/todos"), [2, 1]);
+        assert_eq!(ids(&client, "/api/todos?completed=true"), [2]);
+        assert_eq!(ids(&client, "/api/todos?completed=false"), [1]);
+
+        for value in &["yes", "1"] {
+            let mut response = client
+                .get(format!("/api/todos?completed={}", value))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+            assert!(response.body_string().unwrap().contains("`completed`"));
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let client = Client::new(test_rocket()).unwrap();
+        let counts = || client.get("/api/todos/count").dispatch().body_string();
+
+        assert_eq!(
+            counts().unwrap(),
+            r#"{"total":0,"completed":0,"pending":0}"#
+        );
+
+        for description in &["one", "two", "three", "four", "five"] {
+            create(&client, description);
+        }
+        client.put("/api/todos/2").dispatch();
+        client.put("/api/todos/5").dispatch();
+
+        assert_eq!(
+            counts().unwrap(),
+            r#"{"total":5,"completed":2,"pending":3}"#
+        );
+    }
+
+    #[test]
+    fn sorting() {
+        let client = Client::new(test_rocket()).unwrap();
+        for description in &["banana", "cherry", "apple"] {
+            create(&client, description);
+        }
+        client.put("/api/todos/2").dispatch();
+
+        assert_eq!(ids(&client, "/api/todos"), [3, 2, 1]);
+        assert_eq!(ids(&client, "/api/todos?sort=id"), [1, 2, 3]);
+        assert_eq!(ids(&client, "/api/todos?sort=id&order=asc"), [1, 2, 3]);
+        assert_eq!(ids(&client, "/api/todos?order=asc"), [1, 2, 3]);
+        assert_eq!(ids(&client, "/api/todos?sort=description"), [3, 1, 2]);
+        assert_eq!(
+            ids(&client, "/api/todos?sort=description&order=desc"),
+            [2, 1, 3]
+        );
+        assert_eq!(
+            ids(&client, "/api/todos?sort=completed&order=desc"),
+            [2, 3, 1]
+        );
+        assert_eq!(
+            ids(&client, "/api/todos?sort=description&limit=1&offset=1"),
+            [1]
+        );
+
+        for query in &["sort=slug", "sort=id;DROP%20TABLE%20tasks", "order=up"] {
+            let mut response = client.get(format!("/api/todos?{}", query)).dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+            assert!(response.body_string().unwrap().contains(r#""code":400"#));
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        use serde_json::Value as Json;
+
+        let client = Client::new(test_rocket()).unwrap();
+        let times = |body: String| {
+            let task: Json = serde_json::from_str(&body).unwrap();
+            (task["created_at"].clone(), task["updated_at"].clone())
+        };
+
+        let (created_at, updated_at) = times(create(&client, "timed"));
+        assert!(created_at.is_string());
+        assert_eq!(created_at, updated_at);
+
+        std::thread::sleep(StdDuration::from_millis(5));
+        let toggled = times(client.put("/api/todos/1").dispatch().body_string().unwrap());
+        assert_eq!(toggled.0, created_at);
+        assert_ne!(toggled.1, updated_at);
+
+        std::thread::sleep(StdDuration::from_millis(5));
+        let edited = times(
+            client
+                .patch("/api/todos/1")
+                .header(ContentType::JSON)
+                .body(r#"{ "description": "retimed" }"#)
+                .dispatch()
+                .body_string()
+                .unwrap(),
+        );
+        assert_eq!(edited.0, created_at);
+        assert_ne!(edited.1, toggled.1);
+    }
+
+    #[test]
+    fn unique_descriptions() {
+        use super::task::Task;
+        use diesel::RunQueryDsl;
+
+        let client = Client::new(test_rocket()).unwrap();
+        let post = |description: &str| {
+            client
+                .post("/api/todos")
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"description":"{}"}}"#, description))
+                .dispatch()
+        };
+
+        assert_eq!(post("Pay rent").status(), Status::Created);
+        let mut response = post(" Pay rent ");
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"code":409,"name":"Conflict","message":"Another task already has this description."}"#
+        );
+        // Descriptions differing only in case are the same task
+        assert_eq!(post("pay rent").status(), Status::Conflict);
+        assert_eq!(post("PAY RENT").status(), Status::Conflict);
+        assert_eq!(post("Buy Milk").status(), Status::Created);
+
+        let edit = |id: i32, description: &str| {
+            client
+                .patch(format!("/api/todos/{}", id))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"description":"{}"}}"#, description))
+                .dispatch()
+                .status()
+        };
+        assert_eq!(edit(2, "pay RENT"), Status::Conflict);
+        assert_eq!(edit(1, "PAY RENT"), Status::Ok);
+        assert_eq!(post("Pay rent").status(), Status::Conflict);
+
+        // Imports skip the rows whose description is taken, even by an earlier row
+        let mut response = client
+            .post("/api/todos/import.csv")
+            .header(ContentType::new("text", "csv"))
+            .body("description\nnew\nPay Rent\nNEW\n")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(summary["inserted"], 1);
+        assert_eq!(summary["failed"][0]["line"], 3);
+        assert_eq!(summary["failed"][1]["line"], 4);
+        assert_eq!(
+            client.get("/api/todos/slug/new").dispatch().status(),
+            Status::Ok
+        );
+
+        // Duplicates from before the indexes are renamed by their migrations, to
+        // descriptions which aren't taken either
+        let conn = super::DbConn::get_one(client.rocket()).unwrap();
+        for _ in 0..2 {
+            diesel_migrations::revert_latest_migration(&*conn).unwrap();
+        }
+        diesel::sql_query(
+            "INSERT INTO tasks (description, slug) \
+             VALUES ('new (5)', 'new-2'), ('new', 'new-3'), ('NEW', 'new-4')",
+        )
+        .execute(&*conn)
+        .unwrap();
+        super::embedded_migrations::run(&*conn).unwrap();
+
+        let description = |id: i32| Task::get_one(id, &conn).unwrap().description;
+        assert_eq!(description(3), "new");
+        assert_eq!(description(4), "new (5)");
+        assert_eq!(description(5), "new (5) (5)");
+        assert_eq!(description(6), "NEW (6)");
+        assert_eq!(post("New").status(), Status::Conflict);
+        assert_eq!(post("new (6)").status(), Status::Conflict);
+    }
+
+    #[test]
+    fn search() {
+        let client = Client::new(test_rocket()).unwrap();
+        for description in &["Buy milk", "Milkshake", "Walk the dog", "100% done", "a_b"] {
+            create(&client, description);
+        }
+
+        let search = |query: &str| ids(&client, &format!("/api/todos/search?q={}", query));
+
+        assert_eq!(search("milk"), [2, 1]);
+        assert_eq!(search("the%20dog"), [3]);
+        assert_eq!(search("%25"), [4]);
+        assert_eq!(search("_"), [5]);
+        assert!(search("cheese").is_empty());
+
+        let mut response = client.get("/api/todos/search?q=milk").dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .starts_with(r#"[{"id":2,"description":"Milkshake""#));
+
+        for uri in &[
+            "/api/todos/search",
+            "/api/todos/search?q=",
+            "/api/todos/search?q=%20",
+        ] {
+            assert_eq!(client.get(*uri).dispatch().status(), Status::BadRequest);
+        }
+    }
+
+    #[test]
+    fn set_completed() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "settle");
+        let put = |body: &str| {
+            let mut response = client
+                .put("/api/todos/1")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch();
+            (response.status(), response.body_string().unwrap())
+        };
+
+        for _ in 0..2 {
+            let (status, body) = put(r#"{ "completed": true }"#);
+            assert_eq!(status, Status::Ok);
+            assert!(body.contains(r#""completed":true"#));
+        }
+
+        let (_, body) = put(r#"{ "completed": false }"#);
+        assert!(body.contains(r#""completed":false"#));
+
+        // Without a body, the task is toggled as before
+        let (_, body) = put("");
+        assert!(body.contains(r#""completed":true"#));
+
+        assert_eq!(put(r#"{ "completed": "#).0, Status::BadRequest);
+        assert_eq!(
+            put(r#"{ "completed": "yes" }"#).0,
+            Status::UnprocessableEntity
+        );
+    }
+
+    #[test]
+    fn update() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "tpyo");
+
+        let patch = |body: &str| {
+            let mut response = client
+                .patch("/api/todos/1")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            response.body_string().unwrap()
+        };
+
+        let body = patch(r#"{"description":"typo"}"#);
+        assert!(body.contains(r#""description":"typo","completed":false,"slug":"tpyo""#));
+
+        let body = patch(r#"{"completed":true}"#);
+        assert!(body.contains(r#""description":"typo","completed":true"#));
+
+        let body = patch(r#"{"description":"fixed","completed":false}"#);
+        assert!(body.contains(r#""description":"fixed","completed":false"#));
+
+        let body = patch("{}");
+        assert!(body.contains(r#""description":"fixed","completed":false"#));
+
+        let response = client
+            .patch("/api/todos/2")
+            .header(ContentType::JSON)
+            .body("{}")
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn recent_views() {
+        let client = Client::new(test_rocket()).unwrap();
+        create(&client, "first");
+        create(&client, "second");
+        create(&client, "third");
+
+        let mut response = client.get("/api/todos/1").dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains(r#""last_viewed_at":null"#));
+        let mut response = client.get("/api/todos/recent-views").dispatch();
+        assert_eq!(response.body_string().unwrap(), "[]");
+
+        client.get("/api/todos/3?touch=true").dispatch();
+        let mut response = client.get("/api/todos/1?touch=true").dispatch();
+        assert!(!response
+            .body_string()
+            .unwrap()
+            .contains(r#""last_viewed_at":null"#));
+
+        let mut response = client.get("/api/todos/recent-views").dispatch();
+        let body = response.body_string().unwrap();
+        let first = body.find(r#""id":1,"#).unwrap();
+        let third = body.find(r#""id":3,"#).unwrap();
+        assert!(first < third);
+        assert!(!body.contains(r#""id":2,"#));
+
+        let response = client.get("/api/todos/4?touch=true").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn description_validation() {
+        let client = Client::new(test_rocket()).unwrap();
+        let post = |description: &str| {
+            client
+                .post("/api/todos")
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"description":{:?}}}"#, description))
+                .dispatch()
+        };
+
+        let mut response = post("  \t ");
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("The description can't be empty."));
+
+        let mut response = post(&"a".repeat(300));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("longer than 255 characters"));
+
+        let mut response = post("  trimmed\n");
+        assert_eq!(response.status(), Status::Created);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains(r#""description":"trimmed""#));
+    }
+
+    #[test]
+    fn creation_warnings() {
+        use super::task::Todo;
+
+        let client = Client::new(test_rocket()).unwrap();
+        let todo = |description: &str| Todo {
+            description: description.to_string(),
+            external_key: None,
+        };
+        let codes = |description: &str| {
+            todo(description)
+                .warnings()
+                .into_iter()
+                .map(|warning| warning.code)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(codes("Water the plants").is_empty());
+        assert!(codes("12:30 -- 13:00").is_empty());
+        assert_eq!(codes(" CALL THE BANK "), ["all_caps"]);
+        assert_eq!(codes("Call the bank!!!"), ["repeated_punctuation"]);
+        assert_eq!(codes(&"a".repeat(121)), ["long_description"]);
+        assert_eq!(
+            codes(&format!("{}...", "A".repeat(120))),
+            ["long_description", "all_caps", "repeated_punctuation"]
+        );
+
+        let mut response = client
+            .post("/api/todos")
+            .header(ContentType::JSON)
+            .body(r#"{ "description": "CALL THE BANK" }"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(response.body_string().unwrap().ends_with(
+            r#""warnings":[{"code":"all_caps","message":"The description is in capital letters."}]}"#
+        ));
+
+        let mut response = client
+            .post("/api/todos?return=minimal")
+            .header(ContentType::JSON)
+            .body(r#"{ "description": "Really???" }"#)
+            .dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .starts_with(r#"{"id":2,"warnings":[{"code":"repeated_punctuation","#));
+    }
+
+    #[test]
+    fn batch_create() {
+        let client = Client::new(test_rocket()).unwrap();
+        let batch = |body: String| {
+            client
+                .post("/api/todos/batch")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+
+        let mut response = batch(String::from(
+            r#"[{"description":"first"},{"description":"second","external_key":"b"}]"#,
+        ));
+        assert_eq!(response.status(), Status::Created);
+        let body = response.body_string().unwrap();
+        assert!(body.starts_with(r#"[{"id":1,"description":"first","#));
+        assert!(body.contains(r#"{"id":2,"description":"second","#));
+
+        let mut response = batch(String::from(
+            r#"[{"description":"third"},{"description":" "}]"#,
+        ));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response.body_string().unwrap().contains("index 1"));
+        assert_eq!(
+            client.get("/api/todos/3").dispatch().status(),
+            Status::NotFound
+        );
+
+        let todos = vec![r#"{"description":"task"}"#; 101].join(",");
+        let mut response = batch(format!("[{}]", todos));
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().unwrap().contains(r#""code":400"#));
+        assert_eq!(
+            client.get("/api/todos/3").dispatch().status(),
+            Status::NotFound
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let client = Client::new(test_rocket()).unwrap();
+        let mut response = client.get("/limits").dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"max_description_len":255,"max_batch_size":100}"#
+        );
+
+        // The configured limits are both reported and enforced
+        let mut extras = HashMap::new();
+        extras.insert("max_description_len", Value::from(5));
+        extras.insert("max_batch_size", Value::from(2));
+        let client = Client::new(test_rocket_with(extras)).unwrap();
+        let mut response = client.get("/limits").dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"max_description_len":5,"max_batch_size":2}"#
+        );
+
+        let send = |request: LocalRequest, body: &str| {
+            request
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .status()
+        };
+        assert_eq!(
+            send(client.post("/api/todos"), r#"{"description":"short"}"#),
+            Status::Created
+        );
+        assert_eq!(
+            send(client.post("/api/todos"), r#"{"description":"longer"}"#),
+            Status::UnprocessableEntity
+        );
+        assert_eq!(
+            send(client.patch("/api/todos/1"), r#"{"description":"longer"}"#),
+            Status::UnprocessableEntity
+        );
+        let batch = r#"[{"description":"a"},{"description":"b"},{"description":"c"}]"#;
+        assert_eq!(
+            send(client.post("/api/todos/batch"), batch),
+            Status::BadRequest
+        );
+
+        let mut response = client
+            .post("/api/todos/import.csv")
+            .header(ContentType::new("text", "csv"))
+            .body("description\nlonger\n")
+            .dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .starts_with(r#"{"inserted":0,"failed":[{"line":2,"#));
+
+        for (name, value) in &[("max_description_len", 0), ("max_batch_size", -1)] {
+            let mut extras = HashMap::new();
+            extras.insert(*name, Value::from(*value));
+            match Client::new(test_rocket_with(extras)) {
+                Err(e) => match e.kind() {
+                    LaunchErrorKind::FailedFairings(_) => {}
+                    kind => panic!("unexpected launch error: {}", kind),
+                },
+                Ok(_) => panic!("`{}` = {} was accepted", name, value),
+            }
+        }
+    }
+
+    #[test]
+    fn delete_completed() {
+        let client = Client::new(test_rocket()).unwrap();
+        for description in &["open", "done", "also done", "locked"] {
+            create(&client, description);
+        }
+        for id in 2..=4 {
+            client.put(format!("/api/todos/{}", id)).dispatch();
+        }
+        client
+            .post("/api/todos/4/lock")
+            .header(bearer("admin"))
+            .dispatch();
+
+        let mut response = client.delete("/api/todos/completed").dispatch();
+        assert_eq!(response.body_string().unwrap(), r#"{"deleted":2}"#);
+
+        let mut response = client.get("/api/todos?format=columnar").dispatch();
+        assert!(response
+            .body_string()
+            .unwrap()
+            .starts_with(r#"{"ids":[4,1],"#));
+
+        let mut response = client.delete("/api/todos/completed").dispatch();
+        assert_eq!(response.body_string().unwrap(), r#"{"deleted":0}"#);
+    }
+
+    #[test]
+    fn error_rate() {
+        use super::outcomes::{Outcomes, Summary};
+
+        let at = |minute: i64| Utc.timestamp(minute * 60 + 30, 0);
+        let outcomes = Outcomes::default();
+        assert_eq!(outcomes.summary(at(100), 5).requests, 0);
+
+        outcomes.record(at(94), true);
+        outcomes.record(at(96), true);
+        outcomes.record(at(98), false);
+        outcomes.record(at(100), false);
+
+        assert_eq!(
+            outcomes.summary(at(100), 5),
+            Summary {
+                minutes: 5,
+                requests: 3,
+                errors: 1,
+                error_rate: 1.0 / 3.0,
+            }
+        );
+
+        // Minute 160 takes over the bucket of minute 100
+        outcomes.record(at(160), false);
+        assert_eq!(outcomes.summary(at(100), 5).requests, 2);
+        assert_eq!(outcomes.summary(at(160), 60).requests, 1);
+
+        let client = Client::new(test_rocket()).unwrap();
+        let admin = bearer("admin");
+        client.get("/").dispatch();
+
+        let mut response = client
+            .get("/admin/error-rate")
+            .header(admin.clone())
+            .dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"minutes":5,"requests":1,"errors":0,"error_rate":0.0}"#
+        );
+        let response = client
+            .get("/admin/error-rate?minutes=61")
+            .header(admin.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Responses replaced by a maintenance window count as the 503s they are
+        let mut window = HashMap::new();
+        window.insert("start", Value::from("2000-01-01T00:00:00Z"));
+        window.insert("end", Value::from("2999-01-01T00:00:00Z"));
+        let mut extras = HashMap::new();
+        extras.insert(
+            "maintenance_windows",
+            Value::from(vec![Value::from(window)]),
+        );
+        let client = Client::new(test_rocket_with(extras)).unwrap();
+        assert_eq!(
+            client.get("/").dispatch().status(),
+            Status::ServiceUnavailable
+        );
+
+        let mut response = client.get("/admin/error-rate").header(admin).dispatch();
+        assert_eq!(
+            response.body_string().unwrap(),
+            r#"{"minutes":5,"requests":1,"errors":1,"error_rate":1.0}"#
+        );
+    }
 }
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use rocket::{
+    config::ConfigError,
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, ContentType, Status},
+    Data, Request, Response, Rocket, State,
+};
+use std::io::Cursor;
+
+use crate::ApiError;
+
+/// Paths served normally during maintenance so the service can be operated
+const BYPASS: &[&str] = &["/admin", "/health/live"];
+
+/// Requests caught by a window are rerouted here. It is one of the
+/// `SERVER_PATHS`, which the frontend doesn't route, and nothing is mounted at
+/// it, so no handler runs before the response is replaced with a 503.
+const REROUTE: &str = "/__maintenance__";
+
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+pub struct Window {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+struct Windows(Vec<Window>);
+
+/// End of the window the request arrived in, if any
+struct Ongoing(Option<DateTime<Utc>>);
+
+/// Serves 503s, with a `Retry-After` pointing at the end of the window, for
+/// requests arriving within one of the `maintenance_windows` configured as
+/// `{ start = "<RFC 3339>", end = "<RFC 3339>" }` tables.
+pub struct Maintenance<C> {
+    clock: C,
+}
+
+impl<C: Clock> Maintenance<C> {
+    pub fn new(clock: C) -> Self {
+        Maintenance { clock }
+    }
+}
+
+fn parse_windows(rocket: &Rocket) -> Result<Vec<Window>, String> {
+    let windows = match rocket.config().get_slice("maintenance_windows") {
+        Ok(windows) => windows,
+        Err(ConfigError::Missing(_)) => return Ok(vec![]),
+        Err(_) => return Err(String::from("`maintenance_windows` must be an array")),
+    };
+
+    windows
+        .iter()
+        .map(|window| {
+            let bound = |key| {
+                window
+                    .get(key)
+                    .and_then(|value| value.as_str())
+                    .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                    .map(|value| value.with_timezone(&Utc))
+                    .ok_or_else(|| format!("`{}` must be an RFC 3339 time", key))
+            };
+            let (start, end) = (bound("start")?, bound("end")?);
+
+            if start < end {
+                Ok(Window { start, end })
+            } else {
+                Err(String::from(
+                    "maintenance windows must end after they start",
+                ))
+            }
+        })
+        .collect()
+}
+
+impl<C: Clock> Fairing for Maintenance<C> {
+    fn info(&self) -> Info {
+        Info {
+            name: "Maintenance Windows",
+            kind: Kind::Attach | Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        match parse_windows(&rocket) {
+            Ok(windows) => Ok(rocket.manage(Windows(windows))),
+            Err(e) => {
+                eprintln!("Invalid `maintenance_windows`: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        if BYPASS
+            .iter()
+            .any(|path| request.uri().path().starts_with(path))
+        {
+            return;
+        }
+
+        let now = self.clock.now();
+        let end = request
+            .guard::<State<Windows>>()
+            .succeeded()
+            .and_then(|windows| {
+                windows
+                    .0
+                    .iter()
+                    .find(|window| window.start <= now && now < window.end)
+                    .map(|window| window.end)
+            });
+
+        if let Some(end) = end {
+            request.local_cache(|| Ongoing(Some(end)));
+            request.set_uri(Origin::parse(REROUTE).unwrap());
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let end = match request.local_cache(|| Ongoing(None)).0 {
+            Some(end) => end,
+            None => return,
+        };
+
+        let retry_after = (end - self.clock.now()).num_seconds().max(1);
+        let body = serde_json::to_string(&*ApiError::with_message(
+            Status::ServiceUnavailable,
+            String::from("The service is down for scheduled maintenance, try again later."),
+        ))
+        .unwrap();
+
+        response.set_status(Status::ServiceUnavailable);
+        response.set_header(ContentType::JSON);
+        response.set_raw_header("Retry-After", retry_after.to_string());
+        response.set_sized_body(Cursor::new(body));
+    }
+}
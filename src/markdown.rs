@@ -0,0 +1,55 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Schemes links and images may use. Links using any other scheme, such as
+/// `javascript:` or `data:`, point to `#` instead. Relative links are kept.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+fn safe_url(url: CowStr) -> CowStr {
+    // Browsers ignore whitespace and control characters within schemes
+    let compact: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect();
+    let scheme = compact
+        .find(|c| c == ':' || c == '/' || c == '?' || c == '#')
+        .filter(|&end| compact[end..].starts_with(':'))
+        .map(|end| compact[..end].to_ascii_lowercase());
+
+    match scheme {
+        Some(ref scheme) if !SAFE_SCHEMES.contains(&scheme.as_str()) => CowStr::Borrowed("#"),
+        _ => url,
+    }
+}
+
+fn sanitize(event: Event) -> Event {
+    match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::End(Tag::Link(kind, url, title)) => {
+            Event::End(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        Event::End(Tag::Image(kind, url, title)) => {
+            Event::End(Tag::Image(kind, safe_url(url), title))
+        }
+        event => event,
+    }
+}
+
+/// Renders CommonMark, plus tables, strikethrough and task lists, to HTML
+/// which is safe to embed in a page. Raw HTML in `text` is escaped and shown
+/// as text rather than rendered, which rules out scripts, event handlers and
+/// styles, and links only keep their target for the `SAFE_SCHEMES`.
+pub fn render(text: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(text, options).map(sanitize);
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events);
+    rendered
+}
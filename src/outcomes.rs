@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response, State,
+};
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Longest window, in minutes, which outcomes are kept for
+pub const WINDOW_MINUTES: i64 = 60;
+
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    /// Minutes since the epoch which the counts are for
+    minute: i64,
+    requests: u64,
+    errors: u64,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Summary {
+    pub minutes: i64,
+    pub requests: u64,
+    /// Responses with a 5xx status
+    pub errors: u64,
+    /// `errors / requests`, or 0 without any requests
+    pub error_rate: f64,
+}
+
+/// Per-minute counts of the responses sent over the last `WINDOW_MINUTES`,
+/// in a ring which reuses the bucket of a minute once it has gone by.
+pub struct Outcomes {
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+impl Default for Outcomes {
+    fn default() -> Self {
+        Outcomes {
+            buckets: Mutex::new(vec![Bucket::default(); WINDOW_MINUTES as usize]),
+        }
+    }
+}
+
+impl Outcomes {
+    pub fn record(&self, at: DateTime<Utc>, error: bool) {
+        let minute = at.timestamp().div_euclid(60);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = &mut buckets[minute.rem_euclid(WINDOW_MINUTES) as usize];
+
+        if bucket.minute != minute {
+            *bucket = Bucket {
+                minute,
+                ..Bucket::default()
+            };
+        }
+        bucket.requests += 1;
+        if error {
+            bucket.errors += 1;
+        }
+    }
+
+    /// Counts over the `minutes`, up to `WINDOW_MINUTES`, ending with the one
+    /// `at` is in
+    pub fn summary(&self, at: DateTime<Utc>, minutes: i64) -> Summary {
+        let now = at.timestamp().div_euclid(60);
+        let buckets = self.buckets.lock().unwrap();
+        let (requests, errors) = buckets
+            .iter()
+            .filter(|bucket| now - minutes < bucket.minute && bucket.minute <= now)
+            .fold((0, 0), |(requests, errors), bucket| {
+                (requests + bucket.requests, errors + bucket.errors)
+            });
+
+        Summary {
+            minutes,
+            requests,
+            errors,
+            error_rate: if requests == 0 {
+                0.0
+            } else {
+                errors as f64 / requests as f64
+            },
+        }
+    }
+}
+
+/// Records the outcome of every response in the managed `Outcomes`
+pub struct RecordOutcomes;
+
+impl Fairing for RecordOutcomes {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Outcomes",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if let Some(outcomes) = request.guard::<State<Outcomes>>().succeeded() {
+            outcomes.record(Utc::now(), response.status().code >= 500);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use rocket::{
+    config::ConfigError,
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    Rocket, State,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Attempts allowed per window when `login_rate_limit` is not configured
+pub const DEFAULT_LIMIT: u32 = 5;
+
+/// Length of a window when `login_rate_window_secs` is not configured
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Clients tracked at once. Once that many have a window in progress, the
+/// client whose window started first is forgotten to make room for a new one,
+/// rather than turning new clients away.
+pub const MAX_CLIENTS: usize = 10_000;
+
+/// Counts the login attempts of each client IP over fixed windows which start
+/// with their first attempt. Kept in managed state.
+pub struct LoginLimiter {
+    pub limit: u32,
+    pub window: Duration,
+    /// Proxies whose `X-Real-IP` header is trusted to name the client
+    pub trusted_proxies: Vec<IpAddr>,
+    attempts: Mutex<Attempts>,
+}
+
+struct Attempts {
+    /// Attempts in the current window of each client, and when it started
+    clients: HashMap<IpAddr, (u32, Instant)>,
+    /// When the clients whose windows had ended were last forgotten
+    swept: Instant,
+}
+
+/// Seconds until a rate-limited client may try again, for the 429 catcher
+pub struct RetryAfter(pub Option<u64>);
+
+impl LoginLimiter {
+    pub fn new(limit: u32, window: Duration, trusted_proxies: Vec<IpAddr>) -> Self {
+        LoginLimiter {
+            limit,
+            window,
+            trusted_proxies,
+            attempts: Mutex::new(Attempts {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn from_config(rocket: &Rocket) -> Result<Self, String> {
+        let limit = match rocket.config().get_int("login_rate_limit") {
+            Ok(limit) if limit > 0 => limit as u32,
+            Err(ConfigError::Missing(_)) => DEFAULT_LIMIT,
+            _ => {
+                return Err(String::from(
+                    "`login_rate_limit` must be a positive integer",
+                ))
+            }
+        };
+        let window = match rocket.config().get_int("login_rate_window_secs") {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs as u64),
+            Err(ConfigError::Missing(_)) => DEFAULT_WINDOW,
+            _ => {
+                return Err(String::from(
+                    "`login_rate_window_secs` must be a positive integer",
+                ))
+            }
+        };
+
+        let trusted_proxies = match rocket.config().get_slice("login_trusted_proxies") {
+            Ok(proxies) => proxies
+                .iter()
+                .map(|proxy| proxy.as_str().and_then(|proxy| proxy.parse().ok()))
+                .collect::<Option<_>>()
+                .ok_or_else(|| String::from("trusted proxies must be IP addresses"))?,
+            Err(ConfigError::Missing(_)) => vec![],
+            Err(_) => return Err(String::from("`login_trusted_proxies` must be an array")),
+        };
+
+        Ok(LoginLimiter::new(limit, window, trusted_proxies))
+    }
+
+    /// The client a request is counted against: its remote address, unless that
+    /// is a trusted proxy which names the client in `X-Real-IP`
+    pub fn client(&self, request: &Request) -> Option<IpAddr> {
+        let remote = request.remote()?.ip();
+        if self.trusted_proxies.contains(&remote) {
+            Some(request.real_ip().unwrap_or(remote))
+        } else {
+            Some(remote)
+        }
+    }
+
+    /// Records an attempt by `client` at `now`, unless it has used up its
+    /// window: then the time left in that window is returned instead.
+    pub fn attempt(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut attempts = self.attempts.lock().expect("login attempts lock");
+        let window = self.window;
+        // Other requests may have been counted since `now`, under a later one
+        let elapsed = |started: Instant| now.checked_duration_since(started).unwrap_or_default();
+        let ended = |started: Instant| elapsed(started) >= window;
+        let no_room = |clients: &HashMap<IpAddr, (u32, Instant)>| {
+            clients.len() >= MAX_CLIENTS && !clients.contains_key(&client)
+        };
+        // Forget the clients whose windows have ended once per window, or
+        // sooner when there is no room for a new one
+        if ended(attempts.swept) || no_room(&attempts.clients) {
+            attempts.clients.retain(|_, (_, started)| !ended(*started));
+            attempts.swept = attempts.swept.max(now);
+        }
+
+        if no_room(&attempts.clients) {
+            let first_to_end = attempts
+                .clients
+                .iter()
+                .min_by_key(|(_, (_, started))| *started)
+                .map(|(client, _)| *client);
+            if let Some(first_to_end) = first_to_end {
+                attempts.clients.remove(&first_to_end);
+            }
+        }
+
+        let (count, started) = attempts.clients.entry(client).or_insert((0, now));
+        if ended(*started) {
+            *count = 0;
+            *started = now;
+        }
+        if *count >= self.limit {
+            return Err(window - elapsed(*started));
+        }
+
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// Guard for `POST /login`: fails with a 429 once the client has made
+/// `login_rate_limit` attempts within `login_rate_window_secs`, and with a 400
+/// for requests whose remote address is unknown.
+pub struct LoginAttempt;
+
+impl<'a, 'r> FromRequest<'a, 'r> for LoginAttempt {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let limiter = request.guard::<State<LoginLimiter>>()?;
+        let client = match limiter.client(request) {
+            Some(client) => client,
+            None => return Outcome::Failure((Status::BadRequest, ())),
+        };
+
+        match limiter.attempt(client, Instant::now()) {
+            Ok(()) => Outcome::Success(LoginAttempt),
+            Err(wait) => {
+                // Rounded up, so that clients don't retry a moment too early
+                let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                request.local_cache(|| RetryAfter(Some(secs)));
+                Outcome::Failure((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
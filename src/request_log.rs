@@ -0,0 +1,120 @@
+use log::{Level, LevelFilter};
+use rocket::{
+    config::ConfigError,
+    fairing::{AdHoc, Fairing, Info, Kind},
+    Data, Request, Response, Rocket, State,
+};
+use std::time::Instant;
+
+/// Where request lines go, so that tests can capture them
+pub trait Sink: Send + Sync + 'static {
+    fn log(&self, level: Level, line: &str);
+}
+
+/// Hands request lines to the `log` crate, under the `requests` target. Rocket
+/// installs the global logger, which prints them unless its own `log` level
+/// is too low for theirs.
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn log(&self, level: Level, line: &str) {
+        log::log!(target: "requests", level, "{}", line);
+    }
+}
+
+/// Level the lines are logged at, or `None` when they aren't logged at all
+struct RequestLogLevel(Option<Level>);
+
+/// When the request arrived, and its path before any fairing rerouted it, as
+/// the maintenance windows do
+struct Started {
+    at: Instant,
+    path: String,
+}
+
+fn start<'r>(request: &'r Request) -> &'r Started {
+    request.local_cache(|| Started {
+        at: Instant::now(),
+        path: request.uri().path().to_string(),
+    })
+}
+
+/// Notes the arrival of requests for `RequestLog`. Fairings run in the order
+/// they are attached, and `RequestLog` must be attached after those which
+/// change responses, so this is attached ahead of those which reroute
+/// requests. Without it, `RequestLog` notes the arrival itself.
+pub fn start_timer() -> impl Fairing {
+    AdHoc::on_request("Request Timer", |request, _| {
+        start(request);
+    })
+}
+
+/// Logs one `method=GET path=/api/todos status=200 duration_ms=1.625` line per
+/// request, at the `request_log_level` from the configuration: `"info"` by
+/// default, or `"off"` to log nothing.
+pub struct RequestLog<S> {
+    sink: S,
+}
+
+impl<S: Sink> RequestLog<S> {
+    pub fn new(sink: S) -> Self {
+        RequestLog { sink }
+    }
+}
+
+fn parse_level(rocket: &Rocket) -> Result<Option<Level>, String> {
+    match rocket.config().get_str("request_log_level") {
+        Ok(level) => level
+            .parse::<LevelFilter>()
+            .map(|level| level.to_level())
+            .map_err(|_| format!("unknown level `{}`", level)),
+        Err(ConfigError::Missing(_)) => Ok(Some(Level::Info)),
+        Err(_) => Err(String::from("the level must be a string")),
+    }
+}
+
+impl<S: Sink> Fairing for RequestLog<S> {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Log",
+            kind: Kind::Attach | Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        match parse_level(&rocket) {
+            Ok(level) => Ok(rocket.manage(RequestLogLevel(level))),
+            Err(e) => {
+                eprintln!("Invalid `request_log_level`: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        start(request);
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let level = request
+            .guard::<State<RequestLogLevel>>()
+            .succeeded()
+            .and_then(|level| level.0);
+        let level = match level {
+            Some(level) => level,
+            None => return,
+        };
+
+        let started = start(request);
+        self.sink.log(
+            level,
+            &format!(
+                "method={} path={} status={} duration_ms={:.3}",
+                request.method(),
+                started.path,
+                response.status().code,
+                started.at.elapsed().as_secs_f64() * 1000.0
+            ),
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use rand::Rng;
+use rocket::{config::ConfigError, Rocket};
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// Delay before the first retry, doubled for every retry after it
+const BASE_DELAY: Duration = Duration::from_millis(10);
+
+/// Attempts, including the first one, when `db_retry_attempts` is not configured
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+/// Time spent backing off when `db_retry_max_ms` is not configured
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Errors worth trying again because they may not happen next time, such as
+/// SQLite reporting that the database is busy
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+/// How often, and for how long, transient database errors are retried. Kept in
+/// managed state along with counters of what the retries achieved.
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub max_backoff: Duration,
+    retries: AtomicUsize,
+    recovered: AtomicUsize,
+}
+
+/// Counters reported by `GET /admin/diagnostics`
+#[derive(Serialize)]
+pub struct RetryStats {
+    /// Attempts made after a transient error
+    pub retries: usize,
+    /// Operations which succeeded after at least one retry
+    pub recovered: usize,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            max_backoff,
+            retries: AtomicUsize::new(0),
+            recovered: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn from_config(rocket: &Rocket) -> Result<Self, String> {
+        let max_attempts = match rocket.config().get_int("db_retry_attempts") {
+            Ok(attempts) if attempts > 0 => attempts as u32,
+            Err(ConfigError::Missing(_)) => DEFAULT_MAX_ATTEMPTS,
+            _ => {
+                return Err(String::from(
+                    "`db_retry_attempts` must be a positive integer",
+                ))
+            }
+        };
+        let max_backoff = match rocket.config().get_int("db_retry_max_ms") {
+            Ok(ms) if ms >= 0 => Duration::from_millis(ms as u64),
+            Err(ConfigError::Missing(_)) => DEFAULT_MAX_BACKOFF,
+            _ => {
+                return Err(String::from(
+                    "`db_retry_max_ms` must be a non-negative integer",
+                ))
+            }
+        };
+
+        Ok(RetryPolicy::new(max_attempts, max_backoff))
+    }
+
+    /// Pause before retry number `retry`, counting from 0: half of the doubled
+    /// base delay, plus up to as much again depending on `jitter`, in `[0, 1)`,
+    /// so that colliding requests don't collide again.
+    pub fn delay(retry: u32, jitter: f64) -> Duration {
+        let ceiling = BASE_DELAY * 2u32.saturating_pow(retry);
+        ceiling / 2 + (ceiling / 2).mul_f64(jitter)
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.retries.load(Ordering::SeqCst),
+            recovered: self.recovered.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Runs `op`, retrying it while it fails with a transient error, until
+    /// either `max_attempts` or `max_backoff` runs out.
+    pub fn run<T, E: Transient>(&self, op: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut rng = rand::thread_rng();
+        self.run_with(thread::sleep, || rng.gen(), op)
+    }
+
+    /// `run`, with the ways of waiting and of picking the jitter provided
+    pub fn run_with<T, E: Transient>(
+        &self,
+        mut sleep: impl FnMut(Duration),
+        mut jitter: impl FnMut() -> f64,
+        mut op: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut backoff = Duration::from_secs(0);
+        let mut retry = 0;
+
+        loop {
+            match op() {
+                Ok(value) => {
+                    if retry > 0 {
+                        self.recovered.fetch_add(1, Ordering::SeqCst);
+                    }
+                    return Ok(value);
+                }
+                Err(e) if !e.is_transient() || retry + 1 >= self.max_attempts => return Err(e),
+                Err(e) => {
+                    let delay = RetryPolicy::delay(retry, jitter());
+                    if backoff + delay > self.max_backoff {
+                        return Err(e);
+                    }
+
+                    sleep(delay);
+                    backoff += delay;
+                    retry += 1;
+                    self.retries.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+}
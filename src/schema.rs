@@ -3,5 +3,20 @@ table! {
         id -> Integer,
         description -> Text,
         completed -> Bool,
+        slug -> Text,
+        external_key -> Nullable<Text>,
+        locked -> Bool,
+        last_viewed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        description_key -> Text,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Integer,
+        url -> Text,
+        events -> Text,
     }
 }
@@ -1,8 +1,16 @@
-use diesel::prelude::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, result::DatabaseErrorKind, sqlite::Sqlite};
 use serde::{Deserialize, Serialize};
+use slug::slugify;
+use std::collections::BTreeMap;
 
+use crate::retry::Transient;
 use crate::schema::tasks;
-use crate::schema::tasks::dsl::{completed as task_completed, tasks as all_tasks};
+use crate::schema::tasks::dsl::{
+    completed as task_completed, external_key as task_external_key,
+    last_viewed_at as task_last_viewed_at, locked as task_locked, slug as task_slug,
+    tasks as all_tasks, updated_at as task_updated_at,
+};
 
 #[table_name = "tasks"]
 #[derive(Serialize, Queryable, Identifiable, Debug)]
@@ -10,55 +18,617 @@ pub struct Task {
     pub id: i32,
     pub description: String,
     pub completed: bool,
+    /// Unique, URL-friendly name derived from the original description
+    pub slug: String,
+    /// Client-chosen identifier making creation idempotent
+    pub external_key: Option<String>,
+    /// Locked tasks can't be toggled or deleted until they're unlocked
+    pub locked: bool,
+    /// When the task was last read with `?touch=true`, in UTC
+    pub last_viewed_at: Option<NaiveDateTime>,
+    /// When the task was inserted, in UTC
+    pub created_at: NaiveDateTime,
+    /// When the task was last toggled or edited, in UTC
+    pub updated_at: NaiveDateTime,
+    /// `description_key` of the description, which is unique
+    #[serde(skip)]
+    pub description_key: String,
+}
+
+/// Fields which lists of tasks can be sorted by
+#[derive(FromFormValue, Clone, Copy)]
+pub enum SortField {
+    Id,
+    Description,
+    Completed,
 }
 
 #[table_name = "tasks"]
-#[derive(Deserialize, Insertable)]
+#[derive(Deserialize, Insertable, Clone)]
 pub struct Todo {
     pub description: String,
+    pub external_key: Option<String>,
+}
+
+/// Longest description accepted, in characters, when `max_description_len` is
+/// not configured
+pub const DEFAULT_MAX_DESCRIPTION_LEN: usize = 255;
+
+/// `description` without its surrounding whitespace, which is what gets
+/// stored, provided it is neither empty nor longer than `max_len` characters.
+pub fn validate_description(description: &str, max_len: usize) -> Result<String, String> {
+    let description = description.trim();
+
+    if description.is_empty() {
+        Err(String::from("The description can't be empty."))
+    } else if description.chars().count() > max_len {
+        Err(format!(
+            "The description can't be longer than {} characters.",
+            max_len
+        ))
+    } else {
+        Ok(description.to_string())
+    }
+}
+
+/// Description length, in characters, past which a todo is warned about
+pub const LONG_DESCRIPTION_LEN: usize = 120;
+
+/// A concern about a todo which doesn't stop it being created. The codes are:
+///
+/// - `long_description`: longer than `LONG_DESCRIPTION_LEN` characters
+/// - `all_caps`: written entirely in capital letters
+/// - `repeated_punctuation`: the same punctuation mark three times in a row
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Warning {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Checks behind `Todo::warnings`, each given the trimmed description. New
+/// checks only need adding here, with their code documented on `Warning`.
+const WARNING_CHECKS: &[fn(&str) -> Option<Warning>] =
+    &[long_description, all_caps, repeated_punctuation];
+
+fn long_description(description: &str) -> Option<Warning> {
+    if description.chars().count() > LONG_DESCRIPTION_LEN {
+        Some(Warning {
+            code: "long_description",
+            message: format!(
+                "The description is longer than {} characters.",
+                LONG_DESCRIPTION_LEN
+            ),
+        })
+    } else {
+        None
+    }
+}
+
+fn all_caps(description: &str) -> Option<Warning> {
+    let mut letters = description.chars().filter(|c| c.is_alphabetic()).peekable();
+
+    if letters.peek().is_some() && letters.all(char::is_uppercase) {
+        Some(Warning {
+            code: "all_caps",
+            message: String::from("The description is in capital letters."),
+        })
+    } else {
+        None
+    }
+}
+
+fn repeated_punctuation(description: &str) -> Option<Warning> {
+    let chars: Vec<char> = description.chars().collect();
+
+    if chars
+        .windows(3)
+        .any(|w| w[0].is_ascii_punctuation() && w[0] == w[1] && w[1] == w[2])
+    {
+        Some(Warning {
+            code: "repeated_punctuation",
+            message: String::from("The description repeats punctuation marks."),
+        })
+    } else {
+        None
+    }
+}
+
+impl Todo {
+    pub fn validate(self, max_len: usize) -> Result<Todo, Error> {
+        Ok(Todo {
+            description: validate_description(&self.description, max_len)
+                .map_err(Error::Invalid)?,
+            ..self
+        })
+    }
+
+    /// Concerns about the todo which, unlike those of `validate`, still let it
+    /// be created
+    pub fn warnings(&self) -> Vec<Warning> {
+        let description = self.description.trim();
+        WARNING_CHECKS
+            .iter()
+            .filter_map(|check| check(description))
+            .collect()
+    }
+}
+
+/// Changes to a task, leaving out the fields which stay as they are
+#[table_name = "tasks"]
+#[derive(Deserialize, AsChangeset, Clone)]
+pub struct TaskPatch {
+    pub description: Option<String>,
+    pub completed: Option<bool>,
+    /// Follows the description, which `validate` sets it from
+    #[serde(skip)]
+    pub description_key: Option<String>,
+}
+
+impl TaskPatch {
+    fn is_empty(&self) -> bool {
+        self.description.is_none() && self.completed.is_none()
+    }
+
+    pub fn validate(self, max_len: usize) -> Result<TaskPatch, Error> {
+        let description = match self.description {
+            Some(ref description) => {
+                Some(validate_description(description, max_len).map_err(Error::Invalid)?)
+            }
+            None => None,
+        };
+
+        Ok(TaskPatch {
+            description_key: description
+                .as_ref()
+                .map(|description| description_key(description)),
+            description,
+            ..self
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Locked,
+    /// The input was rejected, for the reason given
+    Invalid(String),
+    /// Another task already has the description
+    Duplicate,
+    Database(diesel::result::Error),
+}
+
+/// SQLite's messages for `SQLITE_BUSY` and `SQLITE_LOCKED`, the codes of a
+/// database which another connection is writing to. Diesel doesn't keep the
+/// codes of errors, only their message, followed by the name of the table for
+/// some `SQLITE_LOCKED` ones.
+const BUSY: &str = "database is locked";
+const LOCKED: &str = "database table is locked";
+
+impl Transient for Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::Database(diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::__Unknown,
+                info,
+            )) => {
+                let message = info.message();
+                message == BUSY
+                    || message == LOCKED
+                    || message.starts_with(&format!("{}: ", LOCKED))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    /// Violations of the `tasks_description_key` unique index, which SQLite
+    /// reports as "UNIQUE constraint failed: tasks.description_key", are told apart
+    /// from those of the other indexes
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => Error::NotFound,
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+                if info.message().contains("tasks.description_key") =>
+            {
+                Error::Duplicate
+            }
+            error => Error::Database(error),
+        }
+    }
+}
+
+/// What makes a description unique: the description with its ASCII letters
+/// lowercased, so that "Buy Milk" and "buy milk" are the same task. Only ASCII
+/// letters are folded, as SQLite's `lower()`, `LIKE` and `NOCASE` collation
+/// do, so that the keys the migrations compute agree with these; other
+/// backends fold other letters too.
+pub fn description_key(description: &str) -> String {
+    description.to_ascii_lowercase()
+}
+
+/// Canonical form of a description used to compare tasks: lowercase, with
+/// surrounding whitespace trimmed and inner runs of whitespace collapsed.
+pub fn normalize(description: &str) -> String {
+    description
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
 }
 
 impl Task {
-    pub fn all(conn: &SqliteConnection) -> Vec<Task> {
+    pub fn all(conn: &SqliteConnection) -> QueryResult<Vec<Task>> {
+        all_tasks.order(tasks::id.desc()).load::<Task>(conn)
+    }
+
+    /// Tasks which are `completed`, or which aren't, or all tasks for `None`
+    fn filter_by_completed(completed: Option<bool>) -> tasks::BoxedQuery<'static, Sqlite> {
+        match completed {
+            Some(completed) => all_tasks.filter(task_completed.eq(completed)).into_boxed(),
+            None => all_tasks.into_boxed(),
+        }
+    }
+
+    /// `limit` tasks, in the order of the `sort` field, after skipping the
+    /// first `offset` ones. Tasks with the same description, or completion, are
+    /// ordered by id, in the same direction, so that pages don't overlap.
+    pub fn page(
+        completed: Option<bool>,
+        sort: SortField,
+        ascending: bool,
+        limit: i64,
+        offset: i64,
+        conn: &SqliteConnection,
+    ) -> QueryResult<Vec<Task>> {
+        let query = Task::filter_by_completed(completed);
+        let query = match (sort, ascending) {
+            (SortField::Id, true) => query.order(tasks::id.asc()),
+            (SortField::Id, false) => query.order(tasks::id.desc()),
+            (SortField::Description, true) => query
+                .order(tasks::description.asc())
+                .then_order_by(tasks::id.asc()),
+            (SortField::Description, false) => query
+                .order(tasks::description.desc())
+                .then_order_by(tasks::id.desc()),
+            (SortField::Completed, true) => query
+                .order(task_completed.asc())
+                .then_order_by(tasks::id.asc()),
+            (SortField::Completed, false) => query
+                .order(task_completed.desc())
+                .then_order_by(tasks::id.desc()),
+        };
+
+        query.limit(limit).offset(offset).load::<Task>(conn)
+    }
+
+    /// Up to `limit` tasks with an id above `after`, in ascending order of id,
+    /// which walks through every task when `after` is the last id seen
+    pub fn after(after: i32, limit: i64, conn: &SqliteConnection) -> QueryResult<Vec<Task>> {
         all_tasks
+            .filter(tasks::id.gt(after))
+            .order(tasks::id.asc())
+            .limit(limit)
+            .load::<Task>(conn)
+    }
+
+    pub fn count(completed: Option<bool>, conn: &SqliteConnection) -> QueryResult<i64> {
+        Task::filter_by_completed(completed)
+            .count()
+            .get_result(conn)
+    }
+
+    /// Numbers of tasks in total, completed and pending, counted within one
+    /// transaction so that they add up
+    pub fn counts(conn: &SqliteConnection) -> QueryResult<(i64, i64, i64)> {
+        conn.transaction(|| {
+            Ok((
+                Task::count(None, conn)?,
+                Task::count(Some(true), conn)?,
+                Task::count(Some(false), conn)?,
+            ))
+        })
+    }
+
+    pub fn get_one(id: i32, conn: &SqliteConnection) -> QueryResult<Task> {
+        all_tasks.find(id).get_result::<Task>(conn)
+    }
+
+    pub fn get_by_slug(slug: &str, conn: &SqliteConnection) -> QueryResult<Task> {
+        all_tasks
+            .filter(task_slug.eq(slug))
+            .get_result::<Task>(conn)
+    }
+
+    /// Records that the task has just been viewed
+    pub fn touch(id: i32, conn: &SqliteConnection) -> QueryResult<()> {
+        diesel::update(all_tasks.find(id))
+            .set(task_last_viewed_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Tasks whose description contains `query`, ignoring the case of ASCII
+    /// letters as SQLite's `LIKE` does, newest first. `%` and `_` in the query
+    /// match themselves rather than acting as wildcards.
+    pub fn search(query: &str, conn: &SqliteConnection) -> QueryResult<Vec<Task>> {
+        let escaped = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+
+        all_tasks
+            .filter(
+                tasks::description
+                    .like(format!("%{}%", escaped))
+                    .escape('\\'),
+            )
             .order(tasks::id.desc())
             .load::<Task>(conn)
-            .unwrap()
     }
 
-    pub fn get_one(id: i32, conn: &SqliteConnection) -> Option<Task> {
-        all_tasks.find(id).get_result::<Task>(conn).ok()
+    /// Up to `limit` viewed tasks, most recently viewed first
+    pub fn recently_viewed(limit: i64, conn: &SqliteConnection) -> QueryResult<Vec<Task>> {
+        all_tasks
+            .filter(task_last_viewed_at.is_not_null())
+            .order((task_last_viewed_at.desc(), tasks::id.desc()))
+            .limit(limit)
+            .load::<Task>(conn)
     }
 
-    pub fn insert(todo: Todo, conn: &SqliteConnection) -> Option<Task> {
+    /// A slug for `description` which no task uses yet: its kebab-cased form,
+    /// suffixed with `-2`, `-3`... when that is already taken.
+    fn unique_slug(description: &str, conn: &SqliteConnection) -> QueryResult<String> {
+        let base = match slugify(description) {
+            ref slug if slug.is_empty() => String::from("task"),
+            slug => slug,
+        };
+        let taken = all_tasks
+            .select(task_slug)
+            .filter(
+                task_slug
+                    .eq(&base)
+                    .or(task_slug.like(format!("{}-%", base))),
+            )
+            .load::<String>(conn)?;
+
+        Ok((1..)
+            .map(|n| match n {
+                1 => base.clone(),
+                n => format!("{}-{}", base, n),
+            })
+            .find(|slug| !taken.contains(slug))
+            .unwrap())
+    }
+
+    /// Fails with `Error::Duplicate` when another task has the description
+    pub fn insert(todo: Todo, conn: &SqliteConnection) -> Result<Task, Error> {
         conn.transaction(|| {
+            let slug = Task::unique_slug(&todo.description, conn)?;
+            let now = Utc::now().naive_utc();
+
             diesel::insert_into(tasks::table)
-                .values(todo)
-                .execute(conn)
-                .and_then(|_| all_tasks.order(tasks::id.desc()).first::<Task>(conn))
+                .values((
+                    tasks::description_key.eq(description_key(&todo.description)),
+                    tasks::description.eq(todo.description),
+                    task_slug.eq(slug),
+                    task_external_key.eq(todo.external_key),
+                    tasks::created_at.eq(now),
+                    task_updated_at.eq(now),
+                ))
+                .execute(conn)?;
+            Ok(all_tasks.order(tasks::id.desc()).first::<Task>(conn)?)
         })
-        .ok()
     }
 
-    pub fn toggle_with_id(id: i32, conn: &SqliteConnection) -> Option<Task> {
+    /// The task which already has `todo`'s external key, if any, along with
+    /// `false`; otherwise the newly inserted task along with `true`. Descriptions
+    /// may be up to `max_len` characters long.
+    pub fn find_or_insert(
+        todo: Todo,
+        max_len: usize,
+        conn: &SqliteConnection,
+    ) -> Result<(Task, bool), Error> {
+        let todo = todo.validate(max_len)?;
+
         conn.transaction(|| {
-            all_tasks
-                .find(id)
-                .get_result::<Task>(conn)
-                .and_then(|task| {
-                    diesel::update(&task)
-                        .set(task_completed.eq(!task.completed))
-                        .execute(conn)
+            let existing = match &todo.external_key {
+                Some(key) => all_tasks
+                    .filter(task_external_key.eq(key))
+                    .first::<Task>(conn)
+                    .optional()?,
+                None => None,
+            };
+
+            match existing {
+                Some(task) => Ok((task, false)),
+                None => Task::insert(todo, conn).map(|task| (task, true)),
+            }
+        })
+        .map_err(Error::from)
+    }
+
+    /// `find_or_insert` for every todo, all or nothing. Invalid todos are
+    /// reported with their index.
+    pub fn insert_many(
+        todos: Vec<Todo>,
+        max_len: usize,
+        conn: &SqliteConnection,
+    ) -> Result<Vec<(Task, bool)>, Error> {
+        let todos = todos
+            .into_iter()
+            .enumerate()
+            .map(|(index, todo)| {
+                todo.validate(max_len).map_err(|e| match e {
+                    Error::Invalid(reason) => {
+                        Error::Invalid(format!("At index {}: {}", index, reason))
+                    }
+                    e => e,
                 })
-                .and_then(|_| all_tasks.find(id).get_result::<Task>(conn))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        conn.transaction(|| {
+            todos
+                .into_iter()
+                .map(|todo| Task::find_or_insert(todo, max_len, conn))
+                .collect()
         })
-        .ok()
     }
 
-    pub fn delete_with_id(id: i32, conn: &SqliteConnection) -> bool {
-        diesel::delete(all_tasks.find(id))
-            .execute(conn)
-            .map(|n| n > 0)
-            .unwrap_or_default()
+    /// The task with `id`, provided it isn't locked
+    fn get_unlocked(id: i32, conn: &SqliteConnection) -> Result<Task, Error> {
+        match all_tasks.find(id).get_result::<Task>(conn)? {
+            task if task.locked => Err(Error::Locked),
+            task => Ok(task),
+        }
+    }
+
+    pub fn toggle_with_id(id: i32, conn: &SqliteConnection) -> Result<Task, Error> {
+        conn.transaction(|| {
+            let task = Task::get_unlocked(id, conn)?;
+            diesel::update(&task)
+                .set((
+                    task_completed.eq(!task.completed),
+                    task_updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)?;
+            Ok(all_tasks.find(id).get_result::<Task>(conn)?)
+        })
+    }
+
+    /// Completes the task, or reopens it, along with whether that changed it.
+    /// Unlike toggling, doing so again leaves the task as it is.
+    pub fn set_completed(
+        id: i32,
+        completed: bool,
+        conn: &SqliteConnection,
+    ) -> Result<(Task, bool), Error> {
+        conn.transaction(|| {
+            let task = Task::get_unlocked(id, conn)?;
+            if task.completed == completed {
+                return Ok((task, false));
+            }
+
+            diesel::update(&task)
+                .set((
+                    task_completed.eq(completed),
+                    task_updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)?;
+            Ok((all_tasks.find(id).get_result::<Task>(conn)?, true))
+        })
+    }
+
+    pub fn delete_with_id(id: i32, conn: &SqliteConnection) -> Result<(), Error> {
+        conn.transaction(|| {
+            let task = Task::get_unlocked(id, conn)?;
+            diesel::delete(&task).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Deletes every completed task which isn't locked, returning how many
+    /// were deleted along with their ids, as read in the same transaction
+    pub fn delete_completed(conn: &SqliteConnection) -> QueryResult<(usize, Vec<i32>)> {
+        let cleared = || task_completed.eq(true).and(task_locked.eq(false));
+
+        conn.transaction(|| {
+            let ids = all_tasks
+                .select(tasks::id)
+                .filter(cleared())
+                .load::<i32>(conn)?;
+            let deleted = diesel::delete(all_tasks.filter(cleared())).execute(conn)?;
+            Ok((deleted, ids))
+        })
+    }
+
+    /// Applies `patch` to the task, keeping its slug. An empty patch changes
+    /// nothing and returns the task as it is. Descriptions may be up to
+    /// `max_len` characters long.
+    pub fn update(
+        id: i32,
+        patch: TaskPatch,
+        max_len: usize,
+        conn: &SqliteConnection,
+    ) -> Result<Task, Error> {
+        let patch = patch.validate(max_len)?;
+
+        conn.transaction(|| {
+            let task = Task::get_unlocked(id, conn)?;
+            if patch.is_empty() {
+                return Ok(task);
+            }
+
+            diesel::update(&task)
+                .set((patch, task_updated_at.eq(Utc::now().naive_utc())))
+                .execute(conn)?;
+            Ok(all_tasks.find(id).get_result::<Task>(conn)?)
+        })
+    }
+
+    pub fn set_locked(id: i32, locked: bool, conn: &SqliteConnection) -> Result<Task, Error> {
+        conn.transaction(|| {
+            let task = all_tasks.find(id).get_result::<Task>(conn)?;
+            diesel::update(&task)
+                .set(task_locked.eq(locked))
+                .execute(conn)?;
+            Ok(all_tasks.find(id).get_result::<Task>(conn)?)
+        })
+    }
+
+    /// Inserts every todo, completing those paired with `true`, returning the
+    /// outcome of each. Todos whose description is taken, by another task or an
+    /// earlier todo, are skipped with `Error::Duplicate`; any other error fails
+    /// the whole import.
+    pub fn import(
+        rows: Vec<(Todo, bool)>,
+        conn: &SqliteConnection,
+    ) -> Result<Vec<Result<Task, Error>>, Error> {
+        let import_one = |todo: Todo, completed: bool| {
+            let task = Task::insert(todo, conn)?;
+            if !completed {
+                return Ok(task);
+            }
+
+            diesel::update(&task)
+                .set(task_completed.eq(true))
+                .execute(conn)?;
+            Ok(all_tasks.find(task.id).get_result::<Task>(conn)?)
+        };
+
+        conn.transaction(|| {
+            rows.into_iter()
+                .map(|(todo, completed)| match import_one(todo, completed) {
+                    Err(Error::Duplicate) => Ok(Err(Error::Duplicate)),
+                    outcome => outcome.map(Ok),
+                })
+                .collect()
+        })
+    }
+
+    /// Groups of active tasks sharing the same normalized description, largest
+    /// groups first, with the ids of each group in ascending order.
+    pub fn find_duplicates(conn: &SqliteConnection) -> QueryResult<Vec<(String, Vec<i32>)>> {
+        let active = all_tasks
+            .filter(task_completed.eq(false))
+            .order(tasks::id.asc())
+            .load::<Task>(conn)?;
+
+        let mut groups: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for task in active {
+            groups
+                .entry(normalize(&task.description))
+                .or_default()
+                .push(task.id);
+        }
+
+        let mut duplicates: Vec<_> = groups
+            .into_iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .collect();
+        duplicates.sort_by(|(_, a), (_, b)| b.len().cmp(&a.len()));
+        Ok(duplicates)
     }
 }
@@ -0,0 +1,310 @@
+use diesel::prelude::*;
+use hyper::{header::ContentType, status::StatusClass, Client};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::schema::webhooks;
+use crate::schema::webhooks::dsl::webhooks as all_webhooks;
+use crate::task::Task;
+
+/// Deliveries waiting to be picked up by the delivery thread; further events
+/// are dropped while it's full
+const QUEUE_SIZE: usize = 256;
+
+/// Deliveries the delivery thread holds on to, until they are due to be sent
+/// or retried; further failed deliveries are dropped rather than retried
+const MAX_SCHEDULED: usize = 1024;
+
+/// Delay before each retry of a failed delivery. A delivery is dropped once
+/// every retry has failed too.
+const BACKOFF: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(25),
+];
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Created,
+    Toggled,
+    Updated,
+    Deleted,
+}
+
+impl Event {
+    const ALL: [Event; 4] = [
+        Event::Created,
+        Event::Toggled,
+        Event::Updated,
+        Event::Deleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Created => "created",
+            Event::Toggled => "toggled",
+            Event::Updated => "updated",
+            Event::Deleted => "deleted",
+        }
+    }
+}
+
+#[table_name = "webhooks"]
+#[derive(Serialize, Queryable, Identifiable, Clone, Debug)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// Comma-separated names of the events delivered to `url`
+    pub events: String,
+}
+
+#[table_name = "webhooks"]
+#[derive(Deserialize, Insertable)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: String,
+}
+
+impl NewWebhook {
+    /// Only plain `http` endpoints can be delivered to, since the client is
+    /// built without TLS, for at least one of the known events.
+    pub fn validate(&self) -> Result<(), String> {
+        let known = |name: &str| Event::ALL.iter().any(|event| event.as_str() == name);
+
+        if !self.url.starts_with("http://") {
+            return Err(String::from(
+                "Webhook URLs must start with `http://`; `https://` is not supported yet.",
+            ));
+        }
+        if self.events.is_empty() || !self.events.split(',').all(|name| known(name.trim())) {
+            return Err(String::from(
+                "`events` must list some of `created`, `toggled`, `updated` and `deleted`.",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Webhook {
+    pub fn all(conn: &SqliteConnection) -> QueryResult<Vec<Webhook>> {
+        all_webhooks.order(webhooks::id.asc()).load::<Webhook>(conn)
+    }
+
+    pub fn count(conn: &SqliteConnection) -> QueryResult<i64> {
+        all_webhooks.count().get_result(conn)
+    }
+
+    pub fn insert(hook: NewWebhook, conn: &SqliteConnection) -> Option<Webhook> {
+        conn.transaction(|| {
+            diesel::insert_into(webhooks::table)
+                .values(hook)
+                .execute(conn)
+                .and_then(|_| {
+                    all_webhooks
+                        .order(webhooks::id.desc())
+                        .first::<Webhook>(conn)
+                })
+        })
+        .ok()
+    }
+
+    pub fn delete_with_id(id: i32, conn: &SqliteConnection) -> bool {
+        diesel::delete(all_webhooks.find(id))
+            .execute(conn)
+            .map(|n| n > 0)
+            .unwrap_or_default()
+    }
+
+    fn wants(&self, event: Event) -> bool {
+        self.events
+            .split(',')
+            .any(|name| name.trim() == event.as_str())
+    }
+}
+
+struct Delivery {
+    url: String,
+    payload: String,
+}
+
+/// A delivery waiting until it is `due`, after `attempts` failed ones
+struct Scheduled {
+    due: Instant,
+    attempts: usize,
+    delivery: Delivery,
+}
+
+/// Ordered by how soon they are due, the soonest being the greatest, since
+/// `BinaryHeap` pops the greatest first
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.due.cmp(&self.due)
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due
+    }
+}
+
+impl Eq for Scheduled {}
+
+/// Hands task events over to a background thread which POSTs them to the
+/// subscribed webhooks, so requests never wait on a delivery.
+pub struct Dispatcher {
+    queue: Mutex<SyncSender<Delivery>>,
+    /// The registered webhooks, loaded on the first event after they changed
+    hooks: RwLock<Option<Vec<Webhook>>>,
+}
+
+impl Dispatcher {
+    /// Spawns the delivery thread, which stops once the dispatcher is dropped.
+    pub fn start() -> Dispatcher {
+        let (queue, deliveries) = mpsc::sync_channel(QUEUE_SIZE);
+        thread::spawn(move || deliver(deliveries));
+
+        Dispatcher {
+            queue: Mutex::new(queue),
+            hooks: RwLock::new(None),
+        }
+    }
+
+    /// Makes the next event load the webhooks again, for when they changed
+    pub fn reload(&self) {
+        *self.hooks.write().unwrap() = None;
+    }
+
+    /// The webhooks, from the database unless they are already loaded. They are
+    /// loaded under the write lock, so that a `reload` waits for the load it
+    /// follows rather than being overwritten by it.
+    fn hooks(&self, conn: &SqliteConnection) -> QueryResult<Vec<Webhook>> {
+        if let Some(hooks) = self.hooks.read().unwrap().as_ref() {
+            return Ok(hooks.clone());
+        }
+
+        let mut cached = self.hooks.write().unwrap();
+        if let Some(hooks) = cached.as_ref() {
+            return Ok(hooks.clone());
+        }
+        let hooks = Webhook::all(conn)?;
+        *cached = Some(hooks.clone());
+        Ok(hooks)
+    }
+
+    /// Queues `event` for every webhook subscribed to it. `task` is `None`
+    /// once the task no longer exists.
+    pub fn notify(&self, event: Event, task_id: i32, task: Option<&Task>, conn: &SqliteConnection) {
+        let hooks = match self.hooks(conn) {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                log::error!(
+                    "Failed to load webhooks, dropping {} event: {}",
+                    event.as_str(),
+                    e
+                );
+                return;
+            }
+        };
+        let payload = json!({ "event": event.as_str(), "task_id": task_id, "task": task });
+        let queue = self.queue.lock().unwrap();
+
+        for hook in hooks.into_iter().filter(|hook| hook.wants(event)) {
+            let delivery = Delivery {
+                url: hook.url,
+                payload: payload.to_string(),
+            };
+
+            if let Err(TrySendError::Full(delivery)) = queue.try_send(delivery) {
+                log::warn!(
+                    "Webhook queue is full, dropping delivery to {}",
+                    delivery.url
+                );
+            }
+        }
+    }
+}
+
+/// Sends each delivery as it arrives, and keeps the failed ones until their
+/// retry is due, so that a webhook which is down doesn't hold up the others.
+fn deliver(deliveries: Receiver<Delivery>) {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(TIMEOUT));
+    client.set_write_timeout(Some(TIMEOUT));
+    let mut scheduled = BinaryHeap::new();
+
+    loop {
+        let received = match scheduled.peek() {
+            Some(Scheduled { due, .. }) => {
+                deliveries.recv_timeout(due.saturating_duration_since(Instant::now()))
+            }
+            None => deliveries
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(delivery) => scheduled.push(Scheduled {
+                due: Instant::now(),
+                attempts: 0,
+                delivery,
+            }),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        while scheduled
+            .peek()
+            .map_or(false, |next| next.due <= Instant::now())
+        {
+            let Scheduled {
+                attempts, delivery, ..
+            } = scheduled.pop().expect("peeked");
+            let delivered = client
+                .post(&delivery.url)
+                .header(ContentType::json())
+                .body(&delivery.payload)
+                .send()
+                .map(|response| response.status.class() == StatusClass::Success)
+                .unwrap_or(false);
+            if delivered {
+                continue;
+            }
+
+            match BACKOFF.get(attempts) {
+                Some(delay) if scheduled.len() < MAX_SCHEDULED => scheduled.push(Scheduled {
+                    due: Instant::now() + *delay,
+                    attempts: attempts + 1,
+                    delivery,
+                }),
+                Some(_) => log::warn!(
+                    "Too many webhook deliveries are waiting, dropping the one to {}",
+                    delivery.url
+                ),
+                None => log::warn!(
+                    "Webhook delivery to {} failed {} times, dropping it",
+                    delivery.url,
+                    attempts + 1
+                ),
+            }
+        }
+    }
+}